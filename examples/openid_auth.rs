@@ -1,8 +1,6 @@
 use std::sync::atomic::AtomicBool;
 
-use anyhow::anyhow;
 use clap::Parser;
-use futures::{FutureExt, select};
 use openidconnect::OAuth2TokenResponse;
 use url::Url;
 use wae::{Hook, WindowHandler, WinitWindow};
@@ -87,44 +85,6 @@ impl WindowHandler for Window {
     }
 }
 
-#[cfg(not(any(target_vendor = "apple", target_os = "linux")))]
-struct BrowserWindow {
-    window: winit::window::Window,
-    close_requested: wae::Signal<()>,
-}
-
-#[cfg(not(any(target_vendor = "apple", target_os = "linux")))]
-impl Default for BrowserWindow {
-    fn default() -> Self {
-        let window = wae::create_window(
-            winit::window::Window::default_attributes().with_title("WebAuth Browser"),
-        )
-        .expect("Failed to create window");
-
-        Self {
-            window,
-            close_requested: wae::Signal::default(),
-        }
-    }
-}
-
-#[cfg(not(any(target_vendor = "apple", target_os = "linux")))]
-impl WinitWindow for BrowserWindow {
-    fn id(&self) -> winit::window::WindowId {
-        self.window.id()
-    }
-}
-
-#[cfg(not(any(target_vendor = "apple", target_os = "linux")))]
-impl WindowHandler for BrowserWindow {
-    fn on_close_requested(&self) -> Result<(), Box<dyn std::error::Error + Send + Sync>> {
-        self.close_requested
-            .set(())
-            .map_err(|_| "Failed to set close signal")?;
-        Ok(())
-    }
-}
-
 struct Application {
     auth_requested: AtomicBool,
     main_window: std::rc::Rc<Window>,
@@ -139,115 +99,45 @@ impl Hook for Application {
             .auth_requested
             .swap(true, std::sync::atomic::Ordering::Relaxed)
         {
-            #[allow(unused)]
             let main_window = self.main_window.clone();
             let auth_url = self.args.auth_url.clone();
             let client_id = self.args.client_id.clone();
             wae::spawn(async move {
-                let options = WebAuthOptions::default();
-                let window;
-                let window_wait;
-                #[cfg(target_os = "macos")]
-                {
-                    use winit::raw_window_handle::{HasWindowHandle, RawWindowHandle};
-
-                    let window_handle = main_window
-                        .window
-                        .window_handle()
-                        .expect("Failed to get window handle");
-
-                    let RawWindowHandle::AppKit(window_handle) = window_handle.as_raw() else {
-                        panic!("Expected AppKit window handle");
-                    };
-                    let view: objc2::rc::Retained<objc2_app_kit::NSView> = unsafe {
-                        use objc2::rc::Retained;
-                        Retained::from_raw(window_handle.ns_view.as_ptr().cast())
-                    }
-                    .expect("Failed to retain NSView");
-                    window = view.window().expect("Failed to get NSWindow from NSView");
-                    window_wait = futures::future::pending::<()>();
-                }
-                #[cfg(target_os = "linux")]
-                {
-                    use gtk::traits::WidgetExt;
-
-                    window = gtk::Window::builder()
-                        .title("WebAuth Example")
-                        .resizable(true)
-                        .default_height(800)
-                        .default_width(800)
-                        .build();
-                    window.show_all();
-                    window_wait = futures::future::pending::<()>();
-                }
-                #[cfg(not(any(target_os = "linux", target_os = "macos")))]
-                {
-                    window = std::rc::Rc::new(BrowserWindow::default());
-                    wae::register_window(&window);
-                    window_wait = window.close_requested.wait();
-                }
-
-                select! {
-                    login = openid::run(
-                        auth_url,
-                        client_id,
-                        Url::parse("com.dungeonfog.foobar:authorized").unwrap(),
-                        async |url| {
-                            select! {
-                                result_url = webauth::authenticate(
-                                    &url,
-                                    "com.dungeonfog.foobar",
-                                    options,
-                                    #[cfg(not(any(target_os = "linux", target_os = "macos")))]
-                                    &window.window,
-                                    #[cfg(any(target_os = "linux", target_os = "macos"))]
-                                    &window,
-                                ).fuse() => {
-                                    let result_url = result_url?;
-                                    let mut code = None;
-                                    let mut state = None;
-                                    for (key, value) in result_url.query_pairs() {
-                                        if key == "code" {
-                                            code = Some(value.to_string());
-                                        } else if key == "state" {
-                                            state = Some(value.to_string());
-                                        }
-                                    }
-                                    if let Some(code) = code
-                                        && let Some(state) = state
-                                    {
-                                        Ok((code, state))
-                                    } else {
-                                        anyhow::bail!(
-                                            "Authorization url doesn't contain both a code and a state"
-                                        );
-                                    }
-                                }
-                                _ = futures_timer::Delay::new(std::time::Duration::from_secs(10)).fuse() => {
-                                    Err(anyhow!("Aborted"))
-                                }
-                            }
-                        },
-                    ).fuse() => {
-                        match login {
-                            Ok((token, _)) => {
-                                tracing::info!("Access token: {:?}", token.access_token().secret());
-                            }
-                            Err(err) => tracing::error!("Authentication failed with {err:?}"),
+                let options = WebAuthOptions {
+                    timeout: Some(std::time::Duration::from_secs(10)),
+                    ..Default::default()
+                };
+                let login = openid::run(
+                    auth_url,
+                    client_id,
+                    Url::parse("com.dungeonfog.foobar:authorized").unwrap(),
+                    async |url| {
+                        let result_url = webauth::authenticate_async(
+                            &url,
+                            "com.dungeonfog.foobar",
+                            options,
+                            &main_window.window,
+                        )
+                        .await?;
+                        let parameters = webauth::CallbackParameters::from_url(result_url)?;
+                        if let Some(code) = parameters.get("code")
+                            && let Some(state) = parameters.get("state")
+                        {
+                            Ok((code.to_string(), state.to_string()))
+                        } else {
+                            anyhow::bail!(
+                                "Authorization url doesn't contain both a code and a state"
+                            );
                         }
+                    },
+                )
+                .await;
+                match login {
+                    Ok((token, _)) => {
+                        tracing::info!("Access token: {:?}", token.access_token().secret());
                     }
-                    _ = window_wait.fuse() => {
-                        tracing::info!("Browser window closed prematurely.");
-                    }
-                }
-
-                #[cfg(target_os = "linux")]
-                {
-                    use gtk::traits::GtkWindowExt;
-
-                    window.close();
+                    Err(err) => tracing::error!("Authentication failed with {err:?}"),
                 }
-                drop(window);
             });
         }
         #[cfg(any(
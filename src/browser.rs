@@ -0,0 +1,329 @@
+//! Authentication in the user's default browser, as recommended by
+//! [RFC 8252](https://www.rfc-editor.org/rfc/rfc8252#section-4).
+//!
+//! The browser is launched through the `org.freedesktop.portal.OpenURI` D-Bus interface when an
+//! xdg-desktop-portal is running on the session bus, and through `xdg-open` otherwise.
+
+use std::{ffi::OsStr, process::Command};
+
+use gtk::{
+    gio::{self, BusType, DBusCallFlags},
+    glib::{self, ToVariant},
+};
+
+use crate::{CallbackMatcher, Error, loopback, pending, timeout::ThreadTimer};
+
+const PORTAL_BUS_NAME: &str = "org.freedesktop.portal.Desktop";
+const PORTAL_OBJECT_PATH: &str = "/org/freedesktop/portal/desktop";
+const PORTAL_OPEN_URI_INTERFACE: &str = "org.freedesktop.portal.OpenURI";
+
+/// How the redirect at the end of the session gets back to the application.
+#[derive(Debug, Clone)]
+pub enum Redirect<'a> {
+    /// Listen on the loopback interface, see [`crate::loopback`]. The `redirect_uri` query
+    /// parameter of the authorization URL is replaced accordingly.
+    Loopback(&'a url::Url),
+    /// Wait for a matching URL, usually of a private-use scheme, handed over via
+    /// [`deliver_callback_url`](crate::deliver_callback_url) once the desktop launches the
+    /// application for it.
+    Handler(CallbackMatcher),
+}
+
+/// Opens the URL in the user's default browser. Failures of `xdg-open` after it started are only
+/// logged, as it might not exit before the browser does.
+pub fn open_url(url: &url::Url) -> Result<(), Error> {
+    match open_url_with_portal(url) {
+        Ok(()) => Ok(()),
+        Err(err) => {
+            tracing::debug!("OpenURI portal not available ({err}), falling back to xdg-open");
+            spawn_opener(OsStr::new("xdg-open"), url)
+        }
+    }
+}
+
+/// Starts `program` with the URL and waits for it on a thread of its own.
+fn spawn_opener(program: &OsStr, url: &url::Url) -> Result<(), Error> {
+    let mut child = Command::new(program).arg(url.as_str()).spawn()?;
+    let program = program.to_owned();
+    std::thread::Builder::new()
+        .name("webauth-xdg-open".to_string())
+        .spawn(move || match child.wait() {
+            Ok(status) if !status.success() => {
+                tracing::warn!(
+                    "{} failed: {}",
+                    program.display(),
+                    Error::OpenBrowser(status)
+                );
+            }
+            Ok(_) => {}
+            Err(err) => tracing::warn!("Failed waiting for {}: {err}", program.display()),
+        })?;
+    Ok(())
+}
+
+fn open_url_with_portal(url: &url::Url) -> Result<(), glib::Error> {
+    let connection = gio::bus_get_sync(BusType::Session, gio::Cancellable::NONE)?;
+    portal_open_uri(&connection, url)
+}
+
+fn portal_open_uri(connection: &gio::DBusConnection, url: &url::Url) -> Result<(), glib::Error> {
+    // OpenURI(s parent_window, s uri, a{sv} options)
+    let parameters = glib::Variant::tuple_from_iter([
+        "".to_variant(),
+        url.as_str().to_variant(),
+        glib::VariantDict::new(None).end(),
+    ]);
+    connection.call_sync(
+        Some(PORTAL_BUS_NAME),
+        PORTAL_OBJECT_PATH,
+        PORTAL_OPEN_URI_INTERFACE,
+        "OpenURI",
+        Some(&parameters),
+        Some(glib::VariantTy::new("(o)").unwrap()),
+        DBusCallFlags::NONE,
+        -1,
+        gio::Cancellable::NONE,
+    )?;
+    Ok(())
+}
+
+pub fn authenticate(
+    auth_url: &url::Url,
+    redirect: Redirect<'_>,
+    options: crate::WebAuthOptions,
+    callback: impl FnOnce(Result<url::Url, Error>) + Send + 'static,
+) -> Result<CancelToken, Error> {
+    tracing::trace!("Opening {auth_url} in the system browser");
+    match redirect {
+        Redirect::Loopback(redirect_uri) => Ok(CancelToken {
+            _loopback: Some(loopback::authenticate(
+                auth_url,
+                redirect_uri,
+                options,
+                open_url,
+                callback,
+            )?),
+            _registration: None,
+            _timeout: None,
+        }),
+        Redirect::Handler(callback_matcher) => {
+            let registration = pending::register(callback_matcher, callback);
+            let timeout = options
+                .timeout
+                .map(|timeout| registration.expire_after(timeout))
+                .transpose()?;
+            open_url(auth_url)?;
+            Ok(CancelToken {
+                _loopback: None,
+                _registration: Some(registration),
+                _timeout: timeout,
+            })
+        }
+    }
+}
+
+/// Dropping this token stops waiting for the redirect. The browser window stays open, as it isn't
+/// under the control of this crate.
+pub struct CancelToken {
+    _loopback: Option<loopback::CancelToken>,
+    _registration: Option<pending::Registration>,
+    _timeout: Option<ThreadTimer>,
+}
+
+pub async fn authenticate_async(
+    auth_url: &url::Url,
+    redirect: Redirect<'_>,
+    options: crate::WebAuthOptions,
+) -> Result<url::Url, Error> {
+    let (sender, receiver) = futures::channel::oneshot::channel();
+
+    let cancel_token = authenticate(auth_url, redirect, options, move |result| {
+        sender.send(result).ok();
+    })?;
+
+    let result = receiver.await.unwrap_or(Err(crate::Error::Aborted));
+    drop(cancel_token);
+
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{
+        collections::HashMap,
+        io::{BufRead, BufReader},
+        process::{Child, Stdio},
+        sync::mpsc,
+        time::Duration,
+    };
+
+    use gtk::gio::{DBusConnection, DBusConnectionFlags, DBusNodeInfo};
+
+    use super::*;
+
+    const PORTAL_INTROSPECTION: &str = r#"
+        <node>
+          <interface name="org.freedesktop.portal.OpenURI">
+            <method name="OpenURI">
+              <arg type="s" name="parent_window" direction="in"/>
+              <arg type="s" name="uri" direction="in"/>
+              <arg type="a{sv}" name="options" direction="in"/>
+              <arg type="o" name="handle" direction="out"/>
+            </method>
+          </interface>
+        </node>"#;
+
+    /// A private session bus, shut down when dropped.
+    struct Bus {
+        daemon: Child,
+        address: String,
+    }
+
+    impl Bus {
+        fn start() -> Option<Self> {
+            let mut daemon = Command::new("dbus-daemon")
+                .args(["--session", "--nofork", "--print-address"])
+                .stdout(Stdio::piped())
+                .spawn()
+                .ok()?;
+            let mut address = String::new();
+            BufReader::new(daemon.stdout.take()?)
+                .read_line(&mut address)
+                .ok()?;
+            Some(Self {
+                daemon,
+                address: address.trim().to_string(),
+            })
+        }
+
+        fn connect(&self) -> DBusConnection {
+            DBusConnection::for_address_sync(
+                &self.address,
+                DBusConnectionFlags::AUTHENTICATION_CLIENT
+                    | DBusConnectionFlags::MESSAGE_BUS_CONNECTION,
+                None,
+                gio::Cancellable::NONE,
+            )
+            .unwrap()
+        }
+    }
+
+    impl Drop for Bus {
+        fn drop(&mut self) {
+            self.daemon.kill().ok();
+            self.daemon.wait().ok();
+        }
+    }
+
+    /// Serves `OpenURI` on the bus from a thread of its own, sending the parameters of every call.
+    fn stand_in_portal(connection: DBusConnection) -> mpsc::Receiver<glib::Variant> {
+        let (sender, receiver) = mpsc::channel();
+        let (ready_sender, ready_receiver) = mpsc::channel();
+        std::thread::spawn(move || {
+            let context = glib::MainContext::new();
+            context
+                .with_thread_default(|| {
+                    let interface = DBusNodeInfo::for_xml(PORTAL_INTROSPECTION)
+                        .unwrap()
+                        .lookup_interface(PORTAL_OPEN_URI_INTERFACE)
+                        .unwrap();
+                    connection
+                        .register_object(
+                            PORTAL_OBJECT_PATH,
+                            &interface,
+                            move |_, _, _, _, _, parameters, invocation| {
+                                sender.send(parameters).ok();
+                                let handle = glib::variant::ObjectPath::try_from(
+                                    "/org/freedesktop/portal/desktop/request/1/webauth",
+                                )
+                                .unwrap();
+                                invocation.return_value(Some(&(handle,).to_variant()));
+                            },
+                            |_, _, _, _, _| unreachable!(),
+                            |_, _, _, _, _, _| false,
+                        )
+                        .unwrap();
+                    connection
+                        .call_sync(
+                            Some("org.freedesktop.DBus"),
+                            "/org/freedesktop/DBus",
+                            "org.freedesktop.DBus",
+                            "RequestName",
+                            Some(&(PORTAL_BUS_NAME, 4u32).to_variant()),
+                            None,
+                            DBusCallFlags::NONE,
+                            -1,
+                            gio::Cancellable::NONE,
+                        )
+                        .unwrap();
+                    ready_sender.send(()).unwrap();
+                    glib::MainLoop::new(Some(&context), false).run();
+                })
+                .unwrap();
+        });
+        ready_receiver.recv().unwrap();
+        receiver
+    }
+
+    #[test]
+    #[ignore = "needs dbus-daemon"]
+    fn open_uri_through_portal() {
+        let bus = Bus::start().expect("dbus-daemon");
+        let calls = stand_in_portal(bus.connect());
+
+        let url = url::Url::parse("https://login.example.com/authorize?state=abc").unwrap();
+        portal_open_uri(&bus.connect(), &url).unwrap();
+
+        let parameters = calls.recv_timeout(Duration::from_secs(5)).unwrap();
+        let (parent_window, uri, options) = parameters
+            .get::<(String, String, HashMap<String, glib::Variant>)>()
+            .unwrap();
+        assert_eq!(parent_window, "");
+        assert_eq!(uri, url.as_str());
+        assert!(options.is_empty());
+    }
+
+    #[test]
+    fn opener_does_not_block() {
+        let directory =
+            std::env::temp_dir().join(format!("webauth-test-{}-opener", std::process::id()));
+        std::fs::create_dir_all(&directory).unwrap();
+        let opened = directory.join("opened");
+        let program = directory.join("xdg-open");
+        // Like xdg-open with some browsers, only exits when the browser does.
+        std::fs::write(
+            &program,
+            format!("#!/bin/sh\necho \"$1\" > {}\nsleep 10\n", opened.display()),
+        )
+        .unwrap();
+        std::fs::set_permissions(
+            &program,
+            std::os::unix::fs::PermissionsExt::from_mode(0o755),
+        )
+        .unwrap();
+
+        let url = url::Url::parse("https://login.example.com/authorize?state=abc").unwrap();
+        let started = std::time::Instant::now();
+        spawn_opener(program.as_os_str(), &url).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+
+        while !std::fs::read_to_string(&opened).is_ok_and(|content| content.ends_with('\n')) {
+            assert!(started.elapsed() < Duration::from_secs(5));
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        assert_eq!(
+            std::fs::read_to_string(&opened).unwrap().trim(),
+            url.as_str()
+        );
+        std::fs::remove_dir_all(&directory).ok();
+    }
+
+    #[test]
+    fn missing_opener() {
+        let url = url::Url::parse("https://login.example.com/authorize").unwrap();
+        assert!(matches!(
+            spawn_opener(OsStr::new("/nonexistent/xdg-open"), &url),
+            Err(Error::Io(_))
+        ));
+    }
+}
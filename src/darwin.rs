@@ -1,6 +1,7 @@
-use std::{cell::RefCell, pin::Pin, task::Poll};
+use std::{cell::RefCell, pin::Pin, rc::Rc, task::Poll};
 
 use block2::RcBlock;
+use dispatch2::{DispatchQueue, DispatchTime, MainThreadBound};
 use objc2::{
     AnyThread, DefinedClass, MainThreadMarker, MainThreadOnly, define_class, msg_send,
     rc::{Retained, autoreleasepool},
@@ -11,24 +12,57 @@ use objc2_authentication_services::{
     ASWebAuthenticationSession, ASWebAuthenticationSessionCallback,
 };
 use objc2_foundation::{NSDictionary, NSError, NSObject, NSObjectProtocol, NSString, NSURL};
+use raw_window_handle::{HasWindowHandle, RawWindowHandle};
 
-use crate::Error;
+use crate::{AuthEvent, AuthSession, CallbackMatcher, Error, session::Parent};
 
+/// See [`AuthSession::builder`] for more settings.
 pub fn authenticate(
     auth_url: &url::Url,
-    callback_scheme: &str,
+    callback_matcher: impl Into<CallbackMatcher>,
     options: crate::WebAuthOptions,
-    window: &objc2::rc::Retained<objc2_app_kit::NSWindow>,
+    window: &impl HasWindowHandle,
     callback: impl FnOnce(Result<url::Url, crate::Error>) + 'static,
 ) -> Result<CancelToken, Error> {
-    // NSWindow is not Send and must not be created on any other thread than the main thread
-    // so this panic should never happen anyways.
-    let mtm = MainThreadMarker::new().expect("NSWindow passed on non-main thread");
-    let callback = RefCell::new(Some(callback));
+    AuthSession::builder()
+        .url(auth_url.clone())
+        .callback_matcher(callback_matcher)
+        .options(options)
+        .parent(window)
+        .start(callback)
+}
+
+/// Starts `session`. ASWebAuthenticationSession doesn't report navigation, so only the result is
+/// sent as event.
+pub(crate) fn start(
+    session: AuthSession<'_>,
+    callback: impl FnOnce(Result<url::Url, crate::Error>) + 'static,
+) -> Result<CancelToken, Error> {
+    let AuthSession {
+        auth_url,
+        callback_matcher,
+        options,
+        parent: Parent::Window(window),
+        events,
+    } = session;
+    // AppKit windows can only be used from the main thread.
+    let mtm = MainThreadMarker::new().ok_or(Error::NeedsToRunOnMainThread)?;
+    let window = ns_window(window)?;
+    let session_callback = session_callback(&callback_matcher)?;
+    let callback: SessionCallback = Rc::new(RefCell::new(Some(Box::new(
+        move |result: Result<url::Url, Error>| {
+            if let Ok(url) = &result {
+                events.emit(AuthEvent::CallbackMatched(url));
+            }
+            events.emit(AuthEvent::SessionFinished(result.as_ref()));
+            callback(result);
+        },
+    ))));
 
+    let completion_callback = callback.clone();
     let completion_handler = RcBlock::new(move |url: *mut NSURL, error: *mut NSError| {
         tracing::trace!("Completion handler called with URL: {url:?}, error: {error:?}");
-        if let Some(callback) = callback.take() {
+        if let Some(callback) = completion_callback.take() {
             if url.is_null() && !error.is_null() {
                 let error = unsafe { objc2::rc::Retained::retain(error) }.unwrap();
                 tracing::error!(
@@ -39,9 +73,14 @@ pub fn authenticate(
             } else if !url.is_null() {
                 if let Some(s) = unsafe { url.as_ref().unwrap().absoluteString() } {
                     autoreleasepool(|pool| match url::Url::parse(unsafe { s.to_str(pool) }) {
-                        Ok(url) => {
+                        // The session only knows the scheme, or host and path, of the matcher.
+                        Ok(url) if callback_matcher.matches(&url) => {
                             callback(Ok(url));
                         }
+                        Ok(url) => {
+                            tracing::error!("ASWebAuthenticationSession returned unmatched {url}");
+                            callback(Err(Error::CallbackMismatch(url)));
+                        }
                         Err(err) => {
                             callback(Err(Error::InvalidUrlInResponse(err)));
                         }
@@ -53,15 +92,13 @@ pub fn authenticate(
         }
     });
 
-    let presentation_context_provider = PresentationContextProvider::new(mtm, window.clone());
+    let presentation_context_provider = PresentationContextProvider::new(mtm, window);
     tracing::trace!("Calling ASWebAuthenticationSession with URL: {auth_url}");
     let session = unsafe {
         ASWebAuthenticationSession::initWithURL_callback_completionHandler(
             ASWebAuthenticationSession::alloc(),
             &NSURL::URLWithString(&NSString::from_str(auth_url.as_str())).unwrap(),
-            &ASWebAuthenticationSessionCallback::callbackWithCustomScheme(&NSString::from_str(
-                callback_scheme,
-            )),
+            &session_callback,
             RcBlock::as_ptr(&completion_handler),
         )
     };
@@ -96,15 +133,107 @@ pub fn authenticate(
         session.start();
     }
 
+    // The session doesn't report navigation, so there's no way to enforce the idle timeout.
+    let on_timeout = options.timeout.map(|timeout| {
+        let session = session.clone();
+        let callback = callback.clone();
+        let on_timeout: Rc<dyn Fn()> = Rc::new(move || {
+            if let Some(callback) = callback.take() {
+                tracing::debug!("ASWebAuthenticationSession timed out");
+                unsafe {
+                    session.cancel();
+                }
+                callback(Err(Error::TimedOut));
+            }
+        });
+        let weak_on_timeout = MainThreadBound::new(Rc::downgrade(&on_timeout), mtm);
+        let delta = i64::try_from(timeout.as_nanos()).unwrap_or(i64::MAX);
+        DispatchQueue::main()
+            .after(DispatchTime::NOW.time(delta), move || {
+                let mtm = MainThreadMarker::new().expect("main queue runs on the main thread");
+                if let Some(on_timeout) = weak_on_timeout.get(mtm).upgrade() {
+                    on_timeout();
+                }
+            })
+            .ok();
+        on_timeout
+    });
+
     Ok(CancelToken {
         session,
         _completion_handler: completion_handler,
+        _on_timeout: on_timeout,
+        callback,
     })
 }
 
+/// The window presenting the session, identified by any of its views.
+fn ns_window(window: &dyn HasWindowHandle) -> Result<Retained<objc2_app_kit::NSWindow>, Error> {
+    let RawWindowHandle::AppKit(handle) = window.window_handle()?.as_raw() else {
+        return Err(Error::UnsupportedWindowHandle);
+    };
+    let view: &objc2_app_kit::NSView = unsafe { handle.ns_view.cast().as_ref() };
+    view.window().ok_or(Error::UnsupportedWindowHandle)
+}
+
+/// ASWebAuthenticationSession can only intercept redirects to a custom scheme, or to an `https`
+/// host and path (which requires the associated domain to be set up for the app).
+fn session_callback(
+    callback_matcher: &CallbackMatcher,
+) -> Result<Retained<ASWebAuthenticationSessionCallback>, Error> {
+    let (scheme, host_path) = match callback_matcher {
+        CallbackMatcher::Scheme(scheme) => (scheme.as_str(), None),
+        CallbackMatcher::SchemeHostPath { scheme, host, path } => {
+            (scheme.as_str(), Some((host.as_str(), path.as_str())))
+        }
+        CallbackMatcher::RedirectUri(redirect_uri) => (
+            redirect_uri.scheme(),
+            redirect_uri
+                .host_str()
+                .map(|host| (host, redirect_uri.path())),
+        ),
+        _ => {
+            return Err(Error::UnsupportedCallbackMatcher(callback_matcher.clone()));
+        }
+    };
+    if scheme.eq_ignore_ascii_case("https") {
+        let Some((host, path)) = host_path else {
+            return Err(Error::UnsupportedCallbackMatcher(callback_matcher.clone()));
+        };
+        Ok(unsafe {
+            ASWebAuthenticationSessionCallback::callbackWithHTTPSHost_path(
+                &NSString::from_str(host),
+                &NSString::from_str(path),
+            )
+        })
+    } else if scheme.eq_ignore_ascii_case("http") {
+        Err(Error::UnsupportedCallbackMatcher(callback_matcher.clone()))
+    } else {
+        Ok(unsafe {
+            ASWebAuthenticationSessionCallback::callbackWithCustomScheme(&NSString::from_str(
+                scheme,
+            ))
+        })
+    }
+}
+
+type SessionCallback = Rc<RefCell<Option<Box<dyn FnOnce(Result<url::Url, Error>)>>>>;
+
+/// Dropping this token cancels the session. If it's still running, the callback receives
+/// [`Error::Aborted`].
 pub struct CancelToken {
     session: Retained<ASWebAuthenticationSession>,
     _completion_handler: RcBlock<dyn Fn(*mut NSURL, *mut NSError)>,
+    /// The timer only holds a weak reference, so dropping the token stops it.
+    _on_timeout: Option<Rc<dyn Fn()>>,
+    callback: SessionCallback,
+}
+
+impl CancelToken {
+    /// Cancels the session, same as dropping the token.
+    pub fn cancel(self) {
+        drop(self);
+    }
 }
 
 impl Drop for CancelToken {
@@ -112,19 +241,24 @@ impl Drop for CancelToken {
         unsafe {
             self.session.cancel();
         }
+        // Call after cancelling, the callback might start another session.
+        if let Some(callback) = self.callback.take() {
+            tracing::trace!("ASWebAuthenticationSession cancelled");
+            callback(Err(Error::Aborted));
+        }
     }
 }
 
 pub fn authenticate_async(
     auth_url: &url::Url,
-    callback_scheme: &str,
+    callback_matcher: impl Into<CallbackMatcher>,
     options: crate::WebAuthOptions,
-    window: &objc2::rc::Retained<objc2_app_kit::NSWindow>,
+    window: &impl HasWindowHandle,
 ) -> AuthenticationFuture {
     let (sender, receiver) = futures::channel::oneshot::channel();
     let token = Some(authenticate(
         auth_url,
-        callback_scheme,
+        callback_matcher,
         options,
         window,
         move |result| {
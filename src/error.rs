@@ -10,8 +10,68 @@ pub enum Error {
     InvalidUrlInResponse(url::ParseError),
     #[error("Aborted")]
     Aborted,
+    #[error("Timed out")]
+    TimedOut,
+    #[error("Cancelled by the user")]
+    UserCancelled,
     #[error("Needs to run on main thread")]
     NeedsToRunOnMainThread,
+    #[error("Not a loopback redirect URI: {0}")]
+    InvalidLoopbackRedirectUri(url::Url),
+    #[error("I/O error: {0}")]
+    Io(#[from] std::io::Error),
+    #[error("Failed to open the system browser: {0}")]
+    OpenBrowser(std::process::ExitStatus),
+    #[error("Invalid URL scheme: {0}")]
+    InvalidScheme(String),
+    #[error("Invalid application ID: {0}")]
+    InvalidAppId(String),
+    #[error("Invalid profile name: {0}")]
+    InvalidProfileName(String),
+    #[error("Window handle not available: {0}")]
+    WindowHandle(#[from] raw_window_handle::HandleError),
+    #[error("Unsupported window handle")]
+    UnsupportedWindowHandle,
+    #[error("Missing session parameter: {0}")]
+    IncompleteSession(&'static str),
+    #[error("Callback matcher not supported by this backend: {0:?}")]
+    UnsupportedCallbackMatcher(crate::CallbackMatcher),
+    #[error("Callback URL doesn't match the callback matcher: {0}")]
+    CallbackMismatch(url::Url),
+    #[error(
+        "Authorization server returned {error}{}",
+        description.as_ref().map(|description| format!(": {description}")).unwrap_or_default()
+    )]
+    Authorization {
+        error: crate::AuthorizationErrorCode,
+        description: Option<String>,
+        uri: Option<String>,
+        state: Option<String>,
+    },
+    #[error("Missing parameter in authorization response: {0}")]
+    MissingResponseParameter(&'static str),
+    #[error("State in response doesn't match the request")]
+    StateMismatch,
+    #[error("Unexpected issuer in authorization response: {0}")]
+    IssuerMismatch(String),
+    #[cfg(feature = "oauth")]
+    #[error("Failed to generate random data: {0}")]
+    Random(getrandom::Error),
+    #[cfg(feature = "oauth")]
+    #[error("Invalid discovery document: {0}")]
+    InvalidDiscoveryDocument(String),
+    #[cfg(feature = "oauth")]
+    #[error("Parameter is set by the request itself: {0}")]
+    ReservedParameter(String),
+    #[cfg(feature = "saml")]
+    #[error("Invalid SAMLResponse: {0}")]
+    InvalidSamlResponse(base64::DecodeError),
+    #[cfg(target_os = "linux")]
+    #[error("Failed to initialize GTK: {0}")]
+    GtkInit(gtk::glib::BoolError),
+    #[cfg(target_os = "linux")]
+    #[error("WebKit error: {0}")]
+    WebKit(gtk::glib::Error),
     #[cfg(not(target_vendor = "apple"))]
     #[error("Wry error: {0}")]
     Wry(#[from] wry::Error),
@@ -0,0 +1,36 @@
+use url::Url;
+
+use crate::Error;
+
+/// What's going on in an authentication session, see
+/// [`AuthSessionBuilder::on_event`](crate::AuthSessionBuilder::on_event).
+///
+/// `ASWebAuthenticationSession` doesn't report anything but the result, so only
+/// [`AuthEvent::CallbackMatched`] and [`AuthEvent::SessionFinished`] are sent on Apple platforms.
+///
+/// Navigation in popups (see [`NewWindowPolicy::Popup`](crate::NewWindowPolicy::Popup)) is
+/// reported like that of the session's web view, as it's subject to the same policy and can end
+/// the session. Their page loads and titles aren't, so these events keep describing the page
+/// shown by the session, like the origin bar of the window opened on Linux.
+#[derive(Debug, Clone, Copy)]
+#[non_exhaustive]
+pub enum AuthEvent<'a> {
+    /// The web view is about to navigate to the URL.
+    NavigationRequested(&'a Url),
+    /// The navigation was allowed by the [`NavigationPolicy`](crate::NavigationPolicy).
+    NavigationAllowed(&'a Url),
+    /// The navigation was cancelled by the [`NavigationPolicy`](crate::NavigationPolicy).
+    NavigationBlocked(&'a Url),
+    /// The web view started loading the page. It might still be redirected, so the URL isn't
+    /// necessarily the one the page is eventually loaded from.
+    PageLoadStarted(&'a Url),
+    /// The web view finished loading the page.
+    PageLoadFinished(&'a Url),
+    /// The title of the page changed.
+    TitleChanged(&'a str),
+    /// The URL matched the callback matcher, the session is about to end.
+    CallbackMatched(&'a Url),
+    /// The session is over, with the callback URL or the error ending it. The callback is called
+    /// right after this.
+    SessionFinished(Result<&'a Url, &'a Error>),
+}
@@ -0,0 +1,134 @@
+//! Authentication for applications that don't use GTK themselves, like those built on winit,
+//! egui or iced.
+//!
+//! GTK is initialized on a dedicated thread the first time a session is started, and every session
+//! runs there in a window of its own (see [`crate::window`]). The application never has to touch
+//! the GTK API or pump its event loop, and the futures returned here are `Send`, so they can be
+//! awaited on any executor.
+//!
+//! Don't use this in applications that initialize GTK themselves, GTK can only run on one thread.
+
+use std::{
+    cell::{Cell, RefCell},
+    collections::HashMap,
+    future::Future,
+    rc::Rc,
+    sync::{
+        OnceLock,
+        atomic::{AtomicU64, Ordering},
+        mpsc,
+    },
+};
+
+use gtk::glib;
+
+use crate::{
+    CallbackMatcher, Error,
+    webview::CancelToken,
+    window::{self, WindowOptions},
+};
+
+static STARTED: OnceLock<Result<(), glib::BoolError>> = OnceLock::new();
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+thread_local! {
+    /// The sessions running on the GTK thread.
+    static SESSIONS: RefCell<HashMap<u64, CancelToken>> = RefCell::new(HashMap::new());
+}
+
+/// Starts the GTK thread, unless it's already running. This happens automatically with the first
+/// session, but calling it early reports a missing display right away.
+pub fn start() -> Result<(), Error> {
+    STARTED
+        .get_or_init(|| {
+            let (sender, receiver) = mpsc::channel();
+            let spawned = std::thread::Builder::new()
+                .name("webauth-gtk".to_string())
+                .spawn(move || {
+                    let initialized = gtk::init();
+                    let failed = initialized.is_err();
+                    sender.send(initialized).ok();
+                    if !failed {
+                        gtk::main();
+                    }
+                });
+            if let Err(err) = spawned {
+                return Err(glib::bool_error!("Failed to spawn the GTK thread: {}", err));
+            }
+            receiver
+                .recv()
+                .unwrap_or_else(|_| Err(glib::bool_error!("The GTK thread exited unexpectedly")))
+        })
+        .clone()
+        .map_err(Error::GtkInit)
+}
+
+/// Like [`window::authenticate_async`], but running on the GTK thread. Dropping the future
+/// cancels the session and closes the window.
+pub fn authenticate_async(
+    auth_url: &url::Url,
+    callback_matcher: impl Into<CallbackMatcher>,
+    options: crate::WebAuthOptions,
+    window_options: WindowOptions,
+) -> impl Future<Output = Result<url::Url, Error>> + Send + 'static {
+    let (sender, receiver) = futures::channel::oneshot::channel();
+    let session = start().map(|()| {
+        let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+        let auth_url = auth_url.clone();
+        let callback_matcher = callback_matcher.into();
+        glib::MainContext::default().invoke(move || {
+            // Shared with the callback, so errors starting the session can be reported as well.
+            let sender = Rc::new(Cell::new(Some(sender)));
+            let callback_sender = sender.clone();
+            let started = window::authenticate(
+                &auth_url,
+                callback_matcher,
+                options,
+                window_options,
+                None::<&gtk::Window>,
+                move |result| {
+                    if let Some(sender) = callback_sender.take() {
+                        sender.send(result).ok();
+                    }
+                    // The callback might be called from a handler of the web view, don't destroy it
+                    // from within.
+                    glib::idle_add_local_once(move || remove_session(id));
+                },
+            );
+            match started {
+                Ok(cancel_token) => {
+                    SESSIONS.with_borrow_mut(|sessions| sessions.insert(id, cancel_token));
+                }
+                Err(err) => {
+                    if let Some(sender) = sender.take() {
+                        sender.send(Err(err)).ok();
+                    }
+                }
+            }
+        });
+        Session { id }
+    });
+
+    async move {
+        let _session = session?;
+        receiver.await.unwrap_or(Err(Error::Aborted))
+    }
+}
+
+/// Cancels the session on the GTK thread when dropped.
+struct Session {
+    id: u64,
+}
+
+impl Drop for Session {
+    fn drop(&mut self) {
+        let id = self.id;
+        glib::MainContext::default().invoke(move || remove_session(id));
+    }
+}
+
+fn remove_session(id: u64) {
+    // Drop outside of the borrow, cancelling calls the callback.
+    let cancel_token = SESSIONS.with_borrow_mut(|sessions| sessions.remove(&id));
+    drop(cancel_token);
+}
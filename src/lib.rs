@@ -1,26 +1,81 @@
+#[cfg(target_os = "linux")]
+pub mod browser;
 #[cfg(target_vendor = "apple")]
 mod darwin;
 mod error;
+mod event;
+#[cfg(target_os = "linux")]
+pub mod gtk_thread;
+pub mod loopback;
+mod matcher;
+mod navigation;
+#[cfg(feature = "oauth")]
+pub mod oauth;
+mod pending;
+mod profile;
+mod response;
+#[cfg(all(feature = "saml", not(target_vendor = "apple")))]
+pub mod saml;
+#[cfg(target_os = "linux")]
+pub mod scheme_handler;
+mod session;
+#[cfg(unix)]
+pub mod single_instance;
+mod timeout;
+mod user_agent;
 #[cfg(any(target_os = "linux", target_os = "windows", target_os = "android"))]
 mod webview;
+#[cfg(target_os = "linux")]
+pub mod window;
 
 #[cfg(target_os = "linux")]
 pub use gtk;
 
-use std::collections::HashMap;
+use std::{collections::HashMap, time::Duration};
 
 pub use error::Error;
+pub use event::AuthEvent;
+pub use matcher::CallbackMatcher;
+pub use navigation::{BlockedNavigation, NavigationPolicy, NewWindowPolicy};
+pub use pending::deliver_callback_url;
+pub use profile::Profile;
+#[cfg(not(target_vendor = "apple"))]
+pub use profile::{BrowsingData, clear_browsing_data, clear_browsing_data_async};
+pub use response::{AuthorizationErrorCode, CallbackParameters};
+pub use session::{AuthSession, AuthSessionBuilder};
+pub use user_agent::{Product, UserAgent};
 
 #[cfg(target_vendor = "apple")]
 pub use darwin::{CancelToken, authenticate, authenticate_async};
 #[cfg(not(target_vendor = "apple"))]
-pub use webview::{CancelToken, authenticate, authenticate_async};
+pub use webview::{
+    CancelToken, authenticate, authenticate_async, authenticate_form_post,
+    authenticate_form_post_async,
+};
+#[cfg(target_os = "linux")]
+pub use webview::{authenticate_in_container, authenticate_in_container_async};
 
-#[cfg(target_os = "windows")]
-pub use wry::raw_window_handle;
+pub use raw_window_handle;
 
 #[derive(Debug, Default)]
 pub struct WebAuthOptions {
+    /// Keeps cookies and other browsing data of the embedded web view in a temporary profile,
+    /// which is deleted when the session is over, instead of the one in
+    /// [`profile`](Self::profile).
     pub prefers_ephemeral_web_browser_session: bool,
     pub additional_header_fields: HashMap<String, String>,
+    /// Ends the session with [`Error::TimedOut`] if it didn't complete within this time. Not
+    /// supported by the embedded web view on Android, where starting a session with a timeout
+    /// fails.
+    pub timeout: Option<Duration>,
+    /// Ends the session with [`Error::TimedOut`] if the web view didn't navigate for this long.
+    /// Ignored where navigation isn't observable, i.e. by `ASWebAuthenticationSession` and the
+    /// system browser. Not supported on Android either.
+    pub idle_timeout: Option<Duration>,
+    /// Where the embedded web view may navigate. Ignored by `ASWebAuthenticationSession` and the
+    /// system browser.
+    pub navigation_policy: NavigationPolicy,
+    pub new_window_policy: NewWindowPolicy,
+    pub user_agent: UserAgent,
+    pub profile: Profile,
 }
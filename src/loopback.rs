@@ -0,0 +1,356 @@
+//! Loopback interface redirection as described in [RFC 8252 §7.3](https://www.rfc-editor.org/rfc/rfc8252#section-7.3).
+//!
+//! Instead of intercepting a private-use URL scheme, a tiny HTTP listener is bound to an ephemeral
+//! port on the loopback interface and the redirect URI in the authorization URL is rewritten to
+//! point at it. The first request to the redirect path completes the session.
+
+use std::{
+    io::{BufRead, BufReader, Write},
+    net::{IpAddr, Ipv4Addr, SocketAddr, TcpListener, TcpStream},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use url::{Host, Url};
+
+use crate::{CallbackMatcher, Error, timeout::ThreadTimer};
+
+const REDIRECT_URI_PARAMETER: &str = "redirect_uri";
+const READ_TIMEOUT: Duration = Duration::from_secs(5);
+const SUCCESS_PAGE: &str = "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Authentication complete</title></head>\
+<body><p>Authentication complete. You can close this window now.</p></body></html>";
+
+/// Starts a loopback redirect session.
+///
+/// `redirect_uri` has to be an `http` URL on a loopback address (`127.0.0.1`, `[::1]` or
+/// `localhost`). If it doesn't specify a port, an ephemeral one is chosen. The `redirect_uri`
+/// query parameter of `auth_url` is replaced by the actual redirect URI (or added if missing), and
+/// the resulting URL is passed to `open_url`, which is responsible for showing it to the user.
+///
+/// `localhost` is only served on `127.0.0.1`, so browsers resolving it to `::1` can't connect.
+/// Prefer the IP literal, as recommended by
+/// [RFC 8252 §8.3](https://www.rfc-editor.org/rfc/rfc8252#section-8.3).
+///
+/// The `callback` is called from a background thread once the redirect arrives.
+pub fn authenticate(
+    auth_url: &url::Url,
+    redirect_uri: &url::Url,
+    options: crate::WebAuthOptions,
+    open_url: impl FnOnce(&url::Url) -> Result<(), Error>,
+    callback: impl FnOnce(Result<url::Url, Error>) + Send + 'static,
+) -> Result<CancelToken, Error> {
+    let ip = loopback_ip(redirect_uri)?;
+    let listener = TcpListener::bind((ip, redirect_uri.port().unwrap_or(0)))?;
+    let local_addr = listener.local_addr()?;
+
+    let mut redirect_uri = redirect_uri.clone();
+    redirect_uri
+        .set_port(Some(local_addr.port()))
+        .map_err(|_| Error::InvalidLoopbackRedirectUri(redirect_uri.clone()))?;
+    let auth_url = substitute_redirect_uri(auth_url, &redirect_uri);
+
+    tracing::trace!("Listening for loopback redirect on {redirect_uri}, opening {auth_url}");
+
+    // Neither custom headers nor private browsing can be requested for a browser we don't control,
+    // and navigation in it isn't observable for the idle timeout.
+    let cancelled = Arc::new(AtomicBool::new(false));
+    let timed_out = Arc::new(AtomicBool::new(false));
+    let timeout = options
+        .timeout
+        .map(|timeout| {
+            let timed_out = timed_out.clone();
+            ThreadTimer::start(timeout, move || {
+                timed_out.store(true, Ordering::SeqCst);
+                TcpStream::connect(local_addr).ok();
+            })
+        })
+        .transpose()?;
+    let cancel_token = CancelToken {
+        local_addr,
+        cancelled: cancelled.clone(),
+        _timeout: timeout,
+    };
+
+    std::thread::Builder::new()
+        .name("webauth-loopback".to_string())
+        .spawn(move || {
+            let callback_matcher = CallbackMatcher::RedirectUri(redirect_uri.clone());
+            if let Some(result) = wait_for_redirect(
+                &listener,
+                &redirect_uri,
+                &callback_matcher,
+                &cancelled,
+                &timed_out,
+            ) {
+                callback(result);
+            }
+        })?;
+
+    open_url(&auth_url)?;
+
+    Ok(cancel_token)
+}
+
+/// Dropping this token stops listening for the redirect.
+pub struct CancelToken {
+    local_addr: SocketAddr,
+    cancelled: Arc<AtomicBool>,
+    _timeout: Option<ThreadTimer>,
+}
+
+impl Drop for CancelToken {
+    fn drop(&mut self) {
+        if !self.cancelled.swap(true, Ordering::SeqCst) {
+            // Wake up the listener thread blocked in accept().
+            TcpStream::connect(self.local_addr).ok();
+        }
+    }
+}
+
+pub async fn authenticate_async(
+    auth_url: &url::Url,
+    redirect_uri: &url::Url,
+    options: crate::WebAuthOptions,
+    open_url: impl FnOnce(&url::Url) -> Result<(), Error>,
+) -> Result<url::Url, Error> {
+    let (sender, receiver) = futures::channel::oneshot::channel();
+
+    let cancel_token = authenticate(auth_url, redirect_uri, options, open_url, move |result| {
+        sender.send(result).ok();
+    })?;
+
+    let result = receiver.await.unwrap_or(Err(crate::Error::Aborted));
+    drop(cancel_token);
+
+    result
+}
+
+fn loopback_ip(redirect_uri: &Url) -> Result<IpAddr, Error> {
+    if redirect_uri.scheme() != "http" {
+        return Err(Error::InvalidLoopbackRedirectUri(redirect_uri.clone()));
+    }
+    match redirect_uri.host() {
+        Some(Host::Ipv4(ip)) if ip.is_loopback() => Ok(IpAddr::V4(ip)),
+        Some(Host::Ipv6(ip)) if ip.is_loopback() => Ok(IpAddr::V6(ip)),
+        Some(Host::Domain(domain)) if domain.eq_ignore_ascii_case("localhost") => {
+            Ok(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        }
+        _ => Err(Error::InvalidLoopbackRedirectUri(redirect_uri.clone())),
+    }
+}
+
+fn substitute_redirect_uri(auth_url: &Url, redirect_uri: &Url) -> Url {
+    let mut substituted = false;
+    let mut pairs: Vec<(String, String)> = auth_url
+        .query_pairs()
+        .map(|(key, value)| {
+            if key == REDIRECT_URI_PARAMETER {
+                substituted = true;
+                (key.into_owned(), redirect_uri.to_string())
+            } else {
+                (key.into_owned(), value.into_owned())
+            }
+        })
+        .collect();
+    if !substituted {
+        pairs.push((REDIRECT_URI_PARAMETER.to_string(), redirect_uri.to_string()));
+    }
+
+    let mut auth_url = auth_url.clone();
+    auth_url.query_pairs_mut().clear().extend_pairs(pairs);
+    auth_url
+}
+
+fn wait_for_redirect(
+    listener: &TcpListener,
+    redirect_uri: &Url,
+    callback_matcher: &CallbackMatcher,
+    cancelled: &AtomicBool,
+    timed_out: &AtomicBool,
+) -> Option<Result<Url, Error>> {
+    loop {
+        let stream = listener.accept().map(|(stream, _)| stream);
+        if cancelled.load(Ordering::SeqCst) {
+            return None;
+        }
+        if timed_out.load(Ordering::SeqCst) {
+            tracing::debug!("Timed out waiting for loopback redirect");
+            return Some(Err(Error::TimedOut));
+        }
+        let mut stream = match stream {
+            Ok(stream) => stream,
+            Err(err) => return Some(Err(err.into())),
+        };
+
+        match read_request_target(&stream) {
+            Ok(Some(target)) => match redirect_uri.join(&target) {
+                Ok(url) if callback_matcher.matches(&url) => {
+                    respond(&mut stream, "200 OK", SUCCESS_PAGE);
+                    return Some(Ok(url));
+                }
+                Ok(_) => respond(&mut stream, "404 Not Found", ""),
+                Err(err) => {
+                    respond(&mut stream, "400 Bad Request", "");
+                    return Some(Err(Error::InvalidUrlInResponse(err)));
+                }
+            },
+            Ok(None) => respond(&mut stream, "400 Bad Request", ""),
+            Err(err) => tracing::debug!("Failed reading loopback request: {err}"),
+        }
+    }
+}
+
+/// Reads the request line and headers, returning the origin-form request target.
+fn read_request_target(stream: &TcpStream) -> std::io::Result<Option<String>> {
+    stream.set_read_timeout(Some(READ_TIMEOUT))?;
+    let mut reader = BufReader::new(stream);
+
+    let mut request_line = String::new();
+    reader.read_line(&mut request_line)?;
+
+    // Drain the headers, we don't need any of them.
+    let mut line = String::new();
+    while reader.read_line(&mut line)? > 0 && !line.trim_end().is_empty() {
+        line.clear();
+    }
+
+    let mut parts = request_line.split_ascii_whitespace();
+    Ok(match (parts.next(), parts.next()) {
+        (Some("GET"), Some(target)) if target.starts_with('/') && !target.starts_with("//") => {
+            Some(target.to_string())
+        }
+        _ => None,
+    })
+}
+
+fn respond(stream: &mut TcpStream, status: &str, body: &str) {
+    let response = format!(
+        "HTTP/1.1 {status}\r\nContent-Type: text/html; charset=utf-8\r\nContent-Length: {}\r\nConnection: close\r\n\r\n{body}",
+        body.len()
+    );
+    if let Err(err) = stream.write_all(response.as_bytes()) {
+        tracing::debug!("Failed writing loopback response: {err}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{io::Read, sync::mpsc};
+
+    use super::*;
+
+    #[test]
+    fn ip() {
+        let ip = |redirect_uri: &str| loopback_ip(&Url::parse(redirect_uri).unwrap()).ok();
+        assert_eq!(
+            ip("http://127.0.0.1/callback"),
+            Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        );
+        assert_eq!(ip("http://[::1]:8080/"), Some("::1".parse().unwrap()));
+        assert_eq!(
+            ip("http://LocalHost/callback"),
+            Some(IpAddr::V4(Ipv4Addr::LOCALHOST))
+        );
+        assert_eq!(ip("https://127.0.0.1/callback"), None);
+        assert_eq!(ip("http://192.168.1.1/callback"), None);
+        assert_eq!(ip("http://localhost.example.com/callback"), None);
+        assert_eq!(ip("com.example.app:/callback"), None);
+    }
+
+    #[test]
+    fn redirect_uri_substitution() {
+        let redirect_uri = Url::parse("http://127.0.0.1:4711/callback").unwrap();
+        let substitute = |auth_url: &str| {
+            substitute_redirect_uri(&Url::parse(auth_url).unwrap(), &redirect_uri).to_string()
+        };
+        assert_eq!(
+            substitute(
+                "https://login.example.com/authorize?client_id=a&redirect_uri=http%3A%2F%2F127.0.0.1%2Fcallback&state=b"
+            ),
+            "https://login.example.com/authorize?client_id=a&redirect_uri=http%3A%2F%2F127.0.0.1%3A4711%2Fcallback&state=b"
+        );
+        assert_eq!(
+            substitute("https://login.example.com/authorize?client_id=a"),
+            "https://login.example.com/authorize?client_id=a&redirect_uri=http%3A%2F%2F127.0.0.1%3A4711%2Fcallback"
+        );
+    }
+
+    /// Starts a session, returning the authorization URL it opened and the channel receiving the
+    /// result.
+    fn start(timeout: Option<Duration>) -> (CancelToken, Url, mpsc::Receiver<Result<Url, Error>>) {
+        let (sender, receiver) = mpsc::channel();
+        let mut opened = None;
+        let cancel_token = authenticate(
+            &Url::parse("https://login.example.com/authorize?state=abc").unwrap(),
+            &Url::parse("http://127.0.0.1/callback").unwrap(),
+            crate::WebAuthOptions {
+                timeout,
+                ..Default::default()
+            },
+            |url| {
+                opened = Some(url.clone());
+                Ok(())
+            },
+            move |result| sender.send(result).unwrap(),
+        )
+        .unwrap();
+        (cancel_token, opened.unwrap(), receiver)
+    }
+
+    fn get(redirect_uri: &Url, target: &str) -> String {
+        let mut stream = TcpStream::connect((
+            redirect_uri.host_str().unwrap(),
+            redirect_uri.port().unwrap(),
+        ))
+        .unwrap();
+        write!(stream, "GET {target} HTTP/1.1\r\nHost: 127.0.0.1\r\n\r\n").unwrap();
+        let mut response = String::new();
+        stream.read_to_string(&mut response).unwrap();
+        response
+    }
+
+    #[test]
+    fn redirect() {
+        let (_cancel_token, auth_url, receiver) = start(None);
+        let redirect_uri = auth_url
+            .query_pairs()
+            .find(|(key, _)| key == REDIRECT_URI_PARAMETER)
+            .map(|(_, value)| Url::parse(&value).unwrap())
+            .unwrap();
+        assert_eq!(redirect_uri.path(), "/callback");
+
+        assert!(get(&redirect_uri, "/favicon.ico").starts_with("HTTP/1.1 404 "));
+        assert!(get(&redirect_uri, "/callback?code=abc&state=abc").starts_with("HTTP/1.1 200 "));
+
+        let url = receiver
+            .recv_timeout(Duration::from_secs(5))
+            .unwrap()
+            .unwrap();
+        assert_eq!(url.path(), "/callback");
+        assert_eq!(url.query(), Some("code=abc&state=abc"));
+        assert_eq!(url.port(), redirect_uri.port());
+    }
+
+    #[test]
+    fn timeout() {
+        let (_cancel_token, _, receiver) = start(Some(Duration::from_millis(50)));
+        assert!(matches!(
+            receiver.recv_timeout(Duration::from_secs(5)).unwrap(),
+            Err(Error::TimedOut)
+        ));
+    }
+
+    #[test]
+    fn cancel() {
+        let (cancel_token, _, receiver) = start(None);
+        drop(cancel_token);
+        // The callback is dropped without being called.
+        assert_eq!(
+            receiver.recv_timeout(Duration::from_secs(5)).err(),
+            Some(mpsc::RecvTimeoutError::Disconnected)
+        );
+    }
+}
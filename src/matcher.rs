@@ -0,0 +1,194 @@
+use std::{fmt, sync::Arc};
+
+use url::Url;
+
+/// Decides which URL ends the session.
+///
+/// A plain string converts into [`CallbackMatcher::Scheme`] and a [`Url`] into
+/// [`CallbackMatcher::RedirectUri`], so most callers can pass either directly.
+#[derive(Clone)]
+pub enum CallbackMatcher {
+    /// Any URL with this scheme, like `com.example.app` for `com.example.app:authorized?code=…`.
+    Scheme(String),
+    /// URLs with this scheme, host and path, like `https://example.com/oauth/callback`. Query and
+    /// fragment are ignored.
+    SchemeHostPath {
+        scheme: String,
+        host: String,
+        path: String,
+    },
+    /// The exact redirect URI as registered with the identity provider. Query parameters of the
+    /// redirect URI itself have to be present in the callback URL, additional ones as well as the
+    /// fragment are ignored.
+    ///
+    /// `ASWebAuthenticationSession` intercepts any URL with a custom scheme, so the session fails
+    /// with [`Error::CallbackMismatch`](crate::Error::CallbackMismatch) if the URL it returns
+    /// doesn't match the rest of the redirect URI.
+    RedirectUri(Url),
+    /// URLs matching any of the contained matchers. Not supported by `ASWebAuthenticationSession`,
+    /// which has to be told a single scheme or host and path up front.
+    Any(Vec<CallbackMatcher>),
+    /// URLs for which the closure returns `true`. Not supported by `ASWebAuthenticationSession`
+    /// either.
+    Custom(Arc<dyn Fn(&Url) -> bool + Send + Sync>),
+}
+
+impl CallbackMatcher {
+    /// An `https` redirect URI with the given host and path, like a universal link. On Apple
+    /// platforms the app needs an associated domain for the host.
+    pub fn https(host: impl Into<String>, path: impl Into<String>) -> Self {
+        Self::SchemeHostPath {
+            scheme: "https".to_string(),
+            host: host.into(),
+            path: path.into(),
+        }
+    }
+
+    pub fn custom(matcher: impl Fn(&Url) -> bool + Send + Sync + 'static) -> Self {
+        Self::Custom(Arc::new(matcher))
+    }
+
+    pub fn matches(&self, url: &Url) -> bool {
+        match self {
+            Self::Scheme(scheme) => url.scheme().eq_ignore_ascii_case(scheme),
+            Self::SchemeHostPath { scheme, host, path } => {
+                url.scheme().eq_ignore_ascii_case(scheme)
+                    && url
+                        .host_str()
+                        .unwrap_or_default()
+                        .eq_ignore_ascii_case(host)
+                    && url.path() == path
+            }
+            Self::RedirectUri(redirect_uri) => {
+                url.scheme().eq_ignore_ascii_case(redirect_uri.scheme())
+                    && url.username() == redirect_uri.username()
+                    && url.password() == redirect_uri.password()
+                    && url
+                        .host_str()
+                        .unwrap_or_default()
+                        .eq_ignore_ascii_case(redirect_uri.host_str().unwrap_or_default())
+                    && url.port_or_known_default() == redirect_uri.port_or_known_default()
+                    && url.path() == redirect_uri.path()
+                    && redirect_uri
+                        .query_pairs()
+                        .all(|pair| url.query_pairs().any(|other| other == pair))
+            }
+            Self::Any(matchers) => matchers.iter().any(|matcher| matcher.matches(url)),
+            Self::Custom(matcher) => matcher(url),
+        }
+    }
+}
+
+impl fmt::Debug for CallbackMatcher {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Scheme(scheme) => f.debug_tuple("Scheme").field(scheme).finish(),
+            Self::SchemeHostPath { scheme, host, path } => f
+                .debug_struct("SchemeHostPath")
+                .field("scheme", scheme)
+                .field("host", host)
+                .field("path", path)
+                .finish(),
+            Self::RedirectUri(redirect_uri) => {
+                f.debug_tuple("RedirectUri").field(redirect_uri).finish()
+            }
+            Self::Any(matchers) => f.debug_tuple("Any").field(matchers).finish(),
+            Self::Custom(_) => f.debug_tuple("Custom").finish_non_exhaustive(),
+        }
+    }
+}
+
+impl From<&str> for CallbackMatcher {
+    fn from(scheme: &str) -> Self {
+        Self::Scheme(scheme.to_string())
+    }
+}
+
+impl From<String> for CallbackMatcher {
+    fn from(scheme: String) -> Self {
+        Self::Scheme(scheme)
+    }
+}
+
+impl From<Url> for CallbackMatcher {
+    fn from(redirect_uri: Url) -> Self {
+        Self::RedirectUri(redirect_uri)
+    }
+}
+
+impl From<&Url> for CallbackMatcher {
+    fn from(redirect_uri: &Url) -> Self {
+        Self::RedirectUri(redirect_uri.clone())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn scheme_is_case_insensitive() {
+        let matcher = CallbackMatcher::from("com.Example.App");
+        assert!(matcher.matches(&url("com.example.app:authorized?code=1")));
+        assert!(matcher.matches(&url("COM.EXAMPLE.APP:/callback")));
+        assert!(!matcher.matches(&url("com.example.other:authorized")));
+        assert!(!matcher.matches(&url("https://com.example.app/")));
+    }
+
+    #[test]
+    fn scheme_host_path() {
+        let matcher = CallbackMatcher::https("Example.com", "/oauth/callback");
+        assert!(matcher.matches(&url("https://example.com/oauth/callback?code=1#state")));
+        assert!(matcher.matches(&url("HTTPS://EXAMPLE.COM/oauth/callback")));
+        assert!(!matcher.matches(&url("http://example.com/oauth/callback")));
+        assert!(!matcher.matches(&url("https://evil.com/oauth/callback")));
+        assert!(!matcher.matches(&url("https://example.com.evil.com/oauth/callback")));
+        assert!(!matcher.matches(&url("https://example.com/oauth/callback/")));
+        assert!(!matcher.matches(&url("https://example.com/OAuth/callback")));
+    }
+
+    #[test]
+    fn redirect_uri_with_query_and_fragment() {
+        let matcher = CallbackMatcher::from(url("http://127.0.0.1:8080/callback?client=desktop"));
+        assert!(matcher.matches(&url("http://127.0.0.1:8080/callback?client=desktop&code=1")));
+        assert!(matcher.matches(&url(
+            "http://127.0.0.1:8080/callback?code=1&client=desktop#x"
+        )));
+        assert!(!matcher.matches(&url("http://127.0.0.1:8080/callback?code=1")));
+        assert!(!matcher.matches(&url("http://127.0.0.1:8080/callback?client=mobile")));
+        assert!(!matcher.matches(&url("http://127.0.0.1:8081/callback?client=desktop")));
+        assert!(!matcher.matches(&url("http://user@127.0.0.1:8080/callback?client=desktop")));
+
+        let matcher = CallbackMatcher::from(url("https://example.com/callback"));
+        assert!(matcher.matches(&url("https://example.com:443/callback#code=1")));
+    }
+
+    #[test]
+    fn opaque_redirect_uri() {
+        let matcher = CallbackMatcher::from(url("com.example.app:authorized"));
+        assert!(matcher.matches(&url("com.example.app:authorized?code=1")));
+        assert!(matcher.matches(&url("Com.Example.App:authorized")));
+        assert!(!matcher.matches(&url("com.example.app:cancelled?code=1")));
+        assert!(!matcher.matches(&url("com.example.app:/authorized")));
+
+        let matcher = CallbackMatcher::from(url("com.example.app:/oauth"));
+        assert!(matcher.matches(&url("com.example.app:/oauth?code=1")));
+        assert!(!matcher.matches(&url("com.example.app://oauth")));
+    }
+
+    #[test]
+    fn any_and_custom() {
+        let matcher = CallbackMatcher::Any(vec![
+            CallbackMatcher::from("com.example.app"),
+            CallbackMatcher::custom(|url| url.path().ends_with("/done")),
+        ]);
+        assert!(matcher.matches(&url("com.example.app:authorized")));
+        assert!(matcher.matches(&url("https://example.com/login/done")));
+        assert!(!matcher.matches(&url("https://example.com/login")));
+        assert!(!CallbackMatcher::Any(Vec::new()).matches(&url("com.example.app:authorized")));
+    }
+}
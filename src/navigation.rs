@@ -0,0 +1,191 @@
+use url::Url;
+
+/// Restricts where the embedded web view may navigate, so the session can't be used to lead the
+/// user to arbitrary sites.
+///
+/// Origins are given as `scheme://host[:port]`, like `https://login.example.com`. A leading `*.`
+/// in the host matches all of its subdomains, like `https://*.example.com`, but not the domain
+/// itself. Depending on the platform, frames are subject to the policy as well, so the origins of
+/// embedded content like captchas may have to be allowed too.
+///
+/// The callback URL is always allowed, and so is the origin of the authorization URL as long as
+/// it isn't blocked explicitly. URLs that can't be parsed are always blocked.
+#[derive(Debug, Clone, Default)]
+pub struct NavigationPolicy {
+    /// The origins the web view may navigate to, like those of federated identity providers.
+    /// Everything not blocked is allowed if empty.
+    pub allowed_origins: Vec<String>,
+    /// The origins the web view may never navigate to, even if allowed above.
+    pub blocked_origins: Vec<String>,
+    /// What happens to navigation that isn't allowed.
+    pub on_blocked: BlockedNavigation,
+}
+
+/// What happens to navigation outside of the [`NavigationPolicy`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum BlockedNavigation {
+    /// The web view stays on the current page.
+    #[default]
+    Cancel,
+    /// The web view stays on the current page, and the URL is opened in the system browser if
+    /// it's an `http` or `https` URL.
+    OpenInBrowser,
+}
+
+impl NavigationPolicy {
+    pub(crate) fn allows(&self, auth_url: &Url, url: &Url) -> bool {
+        if url.as_str() == "about:blank" {
+            return true;
+        }
+        if self
+            .blocked_origins
+            .iter()
+            .any(|pattern| origin_matches(pattern, url))
+        {
+            return false;
+        }
+        self.allowed_origins.is_empty()
+            || url.origin() == auth_url.origin()
+            || self
+                .allowed_origins
+                .iter()
+                .any(|pattern| origin_matches(pattern, url))
+    }
+}
+
+fn origin_matches(pattern: &str, url: &Url) -> bool {
+    let (pattern, subdomains) = match pattern.split_once("://*.") {
+        Some((scheme, host)) => (format!("{scheme}://{host}"), true),
+        None => (pattern.to_string(), false),
+    };
+    let Ok(origin) = Url::parse(&pattern) else {
+        tracing::warn!("Invalid origin in navigation policy: {pattern}");
+        return false;
+    };
+    let (Some(host), Some(url_host)) = (origin.host_str(), url.host_str()) else {
+        return false;
+    };
+    // Like in a content security policy, a wildcard doesn't match the domain itself.
+    let host_matches = if subdomains {
+        url_host.len() > host.len()
+            && url_host.as_bytes()[url_host.len() - host.len() - 1] == b'.'
+            && url_host[url_host.len() - host.len()..].eq_ignore_ascii_case(host)
+    } else {
+        url_host.eq_ignore_ascii_case(host)
+    };
+    host_matches
+        && url.scheme() == origin.scheme()
+        && url.port_or_known_default() == origin.port_or_known_default()
+}
+
+/// What happens when a page opens a new window, with `window.open` or a link or form with
+/// `target="_blank"`, like the social login buttons or "forgot password" links of many identity
+/// providers. Ignored by `ASWebAuthenticationSession` and the system browser.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum NewWindowPolicy {
+    /// The page is loaded in the web view of the session instead, so it's subject to the
+    /// [`NavigationPolicy`] and can end the session by navigating to the callback URL.
+    #[default]
+    SameView,
+    /// The page is opened in a separate window with a web view sharing the session's cookies,
+    /// so it can talk to the page that opened it. It can end the session as well, and is closed
+    /// along with it. Only available on Linux, other platforms use [`NewWindowPolicy::SameView`]
+    /// instead.
+    Popup,
+    /// The page is opened in the system browser, if it's an `http` or `https` URL allowed by the
+    /// [`NavigationPolicy`].
+    SystemBrowser,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn url(url: &str) -> Url {
+        Url::parse(url).unwrap()
+    }
+
+    #[test]
+    fn origins() {
+        assert!(origin_matches(
+            "https://login.example.com",
+            &url("https://login.example.com/authorize?x=1")
+        ));
+        assert!(origin_matches(
+            "https://login.example.com",
+            &url("https://LOGIN.example.com:443/")
+        ));
+        assert!(origin_matches(
+            "http://localhost:8080",
+            &url("http://localhost:8080/callback")
+        ));
+        assert!(!origin_matches(
+            "https://login.example.com",
+            &url("http://login.example.com/")
+        ));
+        assert!(!origin_matches(
+            "https://login.example.com",
+            &url("https://login.example.com:8443/")
+        ));
+        assert!(!origin_matches(
+            "https://login.example.com",
+            &url("https://example.com/")
+        ));
+        assert!(!origin_matches(
+            "https://login.example.com",
+            &url("https://login.example.com.evil.com/")
+        ));
+        assert!(!origin_matches(
+            "not an origin",
+            &url("https://example.com/")
+        ));
+        assert!(!origin_matches(
+            "https://example.com",
+            &url("data:text/html,hello")
+        ));
+    }
+
+    #[test]
+    fn wildcard_origins() {
+        let pattern = "https://*.example.com";
+        assert!(origin_matches(pattern, &url("https://login.example.com/")));
+        assert!(origin_matches(pattern, &url("https://a.b.Example.com/")));
+        assert!(!origin_matches(pattern, &url("https://example.com/")));
+        assert!(!origin_matches(pattern, &url("https://evilexample.com/")));
+        assert!(!origin_matches(
+            pattern,
+            &url("https://example.com.evil.com/")
+        ));
+        assert!(!origin_matches(pattern, &url("http://login.example.com/")));
+        assert!(!origin_matches(
+            pattern,
+            &url("https://login.example.com:8443/")
+        ));
+    }
+
+    #[test]
+    fn policy() {
+        let auth_url = url("https://login.example.com/authorize");
+        let policy = NavigationPolicy::default();
+        assert!(policy.allows(&auth_url, &url("https://anywhere.com/")));
+
+        let policy = NavigationPolicy {
+            allowed_origins: vec!["https://*.idp.com".to_string()],
+            blocked_origins: vec!["https://evil.idp.com".to_string()],
+            ..Default::default()
+        };
+        assert!(policy.allows(&auth_url, &url("https://login.example.com/consent")));
+        assert!(policy.allows(&auth_url, &url("https://sso.idp.com/")));
+        assert!(policy.allows(&auth_url, &url("about:blank")));
+        assert!(!policy.allows(&auth_url, &url("https://evil.idp.com/")));
+        assert!(!policy.allows(&auth_url, &url("https://anywhere.com/")));
+        assert!(!policy.allows(&auth_url, &url("http://login.example.com/")));
+
+        let policy = NavigationPolicy {
+            blocked_origins: vec!["https://login.example.com".to_string()],
+            ..Default::default()
+        };
+        assert!(!policy.allows(&auth_url, &url("https://login.example.com/")));
+        assert!(policy.allows(&auth_url, &url("https://anywhere.com/")));
+    }
+}
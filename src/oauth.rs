@@ -0,0 +1,629 @@
+//! The front-channel part of the OAuth 2.0 authorization code flow
+//! ([RFC 6749 §4.1](https://www.rfc-editor.org/rfc/rfc6749#section-4.1)) with PKCE
+//! ([RFC 7636](https://www.rfc-editor.org/rfc/rfc7636)).
+//!
+//! [`authorize`] builds the authorization URL with a fresh `state` and code challenge, runs it
+//! through one of the backends and validates the response. Exchanging the code for tokens is left
+//! to the OAuth or OpenID Connect client of your choice, together with the returned
+//! [`PkceVerifier`].
+//!
+//! [`logout`] does the same for OpenID Connect RP-Initiated Logout, ending the user's session at
+//! the provider.
+
+use base64::{Engine, engine::general_purpose::URL_SAFE_NO_PAD};
+use sha2::{Digest, Sha256};
+use url::Url;
+
+use crate::{CallbackParameters, Error};
+
+/// Everything needed to build the authorization URL.
+#[derive(Debug, Clone)]
+pub struct AuthorizationRequest {
+    pub authorization_endpoint: Url,
+    pub client_id: String,
+    pub redirect_uri: Url,
+    pub scopes: Vec<String>,
+    /// The issuer identifier of the authorization server. If set, an `iss` parameter in the
+    /// response has to match it ([RFC 9207](https://www.rfc-editor.org/rfc/rfc9207)).
+    pub issuer: Option<String>,
+    /// Additional query parameters, like `nonce`, `prompt` or `login_hint`. Parameters set from
+    /// the other fields, and `state` and the code challenge, are rejected by
+    /// [`prepare`](Self::prepare).
+    pub extra_parameters: Vec<(String, String)>,
+}
+
+impl AuthorizationRequest {
+    pub fn new(
+        authorization_endpoint: Url,
+        client_id: impl Into<String>,
+        redirect_uri: Url,
+    ) -> Self {
+        Self {
+            authorization_endpoint,
+            client_id: client_id.into(),
+            redirect_uri,
+            scopes: Vec::new(),
+            issuer: None,
+            extra_parameters: Vec::new(),
+        }
+    }
+
+    pub fn with_scope(mut self, scope: impl Into<String>) -> Self {
+        self.scopes.push(scope.into());
+        self
+    }
+
+    pub fn with_issuer(mut self, issuer: impl Into<String>) -> Self {
+        self.issuer = Some(issuer.into());
+        self
+    }
+
+    pub fn with_parameter(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra_parameters.push((key.into(), value.into()));
+        self
+    }
+
+    /// Generates `state` and PKCE verifier and builds the authorization URL from them.
+    pub fn prepare(&self) -> Result<PreparedAuthorization, Error> {
+        check_parameters(
+            &self.extra_parameters,
+            &[
+                Some("response_type"),
+                Some("client_id"),
+                Some("redirect_uri"),
+                (!self.scopes.is_empty()).then_some("scope"),
+                Some("state"),
+                Some("code_challenge"),
+                Some("code_challenge_method"),
+            ],
+        )?;
+        let state = random_token(16)?;
+        let pkce_verifier = PkceVerifier(random_token(32)?);
+
+        let mut url = self.authorization_endpoint.clone();
+        {
+            let mut query = url.query_pairs_mut();
+            query
+                .append_pair("response_type", "code")
+                .append_pair("client_id", &self.client_id)
+                .append_pair("redirect_uri", self.redirect_uri.as_str());
+            if !self.scopes.is_empty() {
+                query.append_pair("scope", &self.scopes.join(" "));
+            }
+            query
+                .append_pair("state", &state)
+                .append_pair("code_challenge", &pkce_verifier.challenge())
+                .append_pair("code_challenge_method", "S256")
+                .extend_pairs(&self.extra_parameters);
+        }
+
+        Ok(PreparedAuthorization {
+            url,
+            state,
+            issuer: self.issuer.clone(),
+            pkce_verifier,
+        })
+    }
+}
+
+/// An authorization URL together with the secrets needed to validate the response.
+#[derive(Debug)]
+pub struct PreparedAuthorization {
+    url: Url,
+    state: String,
+    issuer: Option<String>,
+    pkce_verifier: PkceVerifier,
+}
+
+impl PreparedAuthorization {
+    /// The URL to open in the browser.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn state(&self) -> &str {
+        &self.state
+    }
+
+    /// Validates the callback URL the session ended with. Error responses of the authorization
+    /// server are returned as [`Error::Authorization`].
+    pub fn finish(self, callback_url: Url) -> Result<(AuthorizationResponse, PkceVerifier), Error> {
+        let parameters = CallbackParameters::from_url(callback_url)?;
+
+        let state = parameters
+            .get("state")
+            .ok_or(Error::MissingResponseParameter("state"))?
+            .to_string();
+        if state != self.state {
+            return Err(Error::StateMismatch);
+        }
+        let iss = parameters.get("iss").map(str::to_string);
+        if let (Some(expected), Some(actual)) = (&self.issuer, &iss)
+            && expected != actual
+        {
+            return Err(Error::IssuerMismatch(actual.clone()));
+        }
+        let code = parameters
+            .get("code")
+            .ok_or(Error::MissingResponseParameter("code"))?
+            .to_string();
+
+        Ok((
+            AuthorizationResponse {
+                code,
+                state,
+                iss,
+                url: parameters.into_url(),
+            },
+            self.pkce_verifier,
+        ))
+    }
+}
+
+/// A successful and validated authorization response.
+#[derive(Debug, Clone)]
+pub struct AuthorizationResponse {
+    pub code: String,
+    pub state: String,
+    pub iss: Option<String>,
+    /// The full callback URL, for any additional parameters.
+    pub url: Url,
+}
+
+/// The PKCE code verifier, to be sent along with the code in the token request.
+#[derive(Clone)]
+pub struct PkceVerifier(String);
+
+impl PkceVerifier {
+    pub fn secret(&self) -> &str {
+        &self.0
+    }
+
+    pub fn into_secret(self) -> String {
+        self.0
+    }
+
+    /// The `S256` code challenge derived from this verifier.
+    pub fn challenge(&self) -> String {
+        URL_SAFE_NO_PAD.encode(Sha256::digest(self.0.as_bytes()))
+    }
+}
+
+impl std::fmt::Debug for PkceVerifier {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str("PkceVerifier([redacted])")
+    }
+}
+
+/// Runs the authorization request through `authenticate`, which is expected to call one of the
+/// backends with the URL it's given, like
+///
+/// ```ignore
+/// let (response, pkce_verifier) = webauth::oauth::authorize(&request, async |url| {
+///     webauth::authenticate_async(&url, redirect_uri.clone(), options, &window).await
+/// })
+/// .await?;
+/// ```
+pub async fn authorize(
+    request: &AuthorizationRequest,
+    authenticate: impl AsyncFnOnce(Url) -> Result<Url, Error>,
+) -> Result<(AuthorizationResponse, PkceVerifier), Error> {
+    let prepared = request.prepare()?;
+    let callback_url = authenticate(prepared.url().clone()).await?;
+    prepared.finish(callback_url)
+}
+
+/// An OpenID Connect RP-Initiated Logout request
+/// ([RP-Initiated Logout 1.0](https://openid.net/specs/openid-connect-rpinitiated-1_0.html)).
+#[derive(Debug, Clone)]
+pub struct LogoutRequest {
+    /// The `end_session_endpoint` from the provider's discovery metadata.
+    pub end_session_endpoint: Url,
+    /// Where the provider redirects to after logging out, which ends the session. It has to be
+    /// registered with the provider.
+    pub post_logout_redirect_uri: Url,
+    /// The ID token of the session to end. Providers usually require it, or at least the client
+    /// ID, to accept the redirect URI.
+    pub id_token_hint: Option<String>,
+    /// The client ID, which tells the provider whose redirect URIs to check without an ID token.
+    pub client_id: Option<String>,
+    /// Additional query parameters, like `logout_hint` or `ui_locales`. Parameters set from the
+    /// other fields, and `state`, are rejected by [`prepare`](Self::prepare).
+    pub extra_parameters: Vec<(String, String)>,
+}
+
+impl LogoutRequest {
+    pub fn new(end_session_endpoint: Url, post_logout_redirect_uri: Url) -> Self {
+        Self {
+            end_session_endpoint,
+            post_logout_redirect_uri,
+            id_token_hint: None,
+            client_id: None,
+            extra_parameters: Vec::new(),
+        }
+    }
+
+    /// Takes the `end_session_endpoint` from the provider's discovery document
+    /// ([OpenID Connect Discovery 1.0 §4][discovery]), the JSON served at
+    /// `/.well-known/openid-configuration`.
+    ///
+    /// [discovery]: https://openid.net/specs/openid-connect-discovery-1_0.html#ProviderConfig
+    pub fn from_discovery_document(
+        document: &str,
+        post_logout_redirect_uri: Url,
+    ) -> Result<Self, Error> {
+        let metadata: serde_json::Value = serde_json::from_str(document)
+            .map_err(|err| Error::InvalidDiscoveryDocument(err.to_string()))?;
+        let end_session_endpoint = metadata
+            .get("end_session_endpoint")
+            .and_then(serde_json::Value::as_str)
+            .ok_or_else(|| {
+                Error::InvalidDiscoveryDocument(
+                    "the provider doesn't support RP-Initiated Logout".to_string(),
+                )
+            })?;
+        let end_session_endpoint = Url::parse(end_session_endpoint).map_err(|err| {
+            Error::InvalidDiscoveryDocument(format!("invalid end_session_endpoint: {err}"))
+        })?;
+        Ok(Self::new(end_session_endpoint, post_logout_redirect_uri))
+    }
+
+    pub fn with_id_token_hint(mut self, id_token: impl Into<String>) -> Self {
+        self.id_token_hint = Some(id_token.into());
+        self
+    }
+
+    pub fn with_client_id(mut self, client_id: impl Into<String>) -> Self {
+        self.client_id = Some(client_id.into());
+        self
+    }
+
+    pub fn with_parameter(mut self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        self.extra_parameters.push((key.into(), value.into()));
+        self
+    }
+
+    /// Generates `state` and builds the logout URL with it.
+    pub fn prepare(&self) -> Result<PreparedLogout, Error> {
+        check_parameters(
+            &self.extra_parameters,
+            &[
+                Some("state"),
+                Some("post_logout_redirect_uri"),
+                self.id_token_hint.as_ref().map(|_| "id_token_hint"),
+                self.client_id.as_ref().map(|_| "client_id"),
+            ],
+        )?;
+        let state = random_token(16)?;
+
+        let mut url = self.end_session_endpoint.clone();
+        {
+            let mut query = url.query_pairs_mut();
+            if let Some(id_token_hint) = &self.id_token_hint {
+                query.append_pair("id_token_hint", id_token_hint);
+            }
+            if let Some(client_id) = &self.client_id {
+                query.append_pair("client_id", client_id);
+            }
+            query
+                .append_pair(
+                    "post_logout_redirect_uri",
+                    self.post_logout_redirect_uri.as_str(),
+                )
+                .append_pair("state", &state)
+                .extend_pairs(&self.extra_parameters);
+        }
+
+        Ok(PreparedLogout { url, state })
+    }
+}
+
+/// A logout URL together with the `state` to expect in the redirect.
+#[derive(Debug)]
+pub struct PreparedLogout {
+    url: Url,
+    state: String,
+}
+
+impl PreparedLogout {
+    /// The URL to open in the browser.
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn state(&self) -> &str {
+        &self.state
+    }
+
+    /// Validates the redirect to the `post_logout_redirect_uri` the session ended with.
+    pub fn finish(self, callback_url: Url) -> Result<(), Error> {
+        let parameters = CallbackParameters::from_url(callback_url)?;
+        let state = parameters
+            .get("state")
+            .ok_or(Error::MissingResponseParameter("state"))?;
+        if state != self.state {
+            return Err(Error::StateMismatch);
+        }
+        Ok(())
+    }
+}
+
+/// Runs the logout request through `authenticate`, just like [`authorize`]. The callback matcher
+/// of the backend has to match the `post_logout_redirect_uri`.
+pub async fn logout(
+    request: &LogoutRequest,
+    authenticate: impl AsyncFnOnce(Url) -> Result<Url, Error>,
+) -> Result<(), Error> {
+    let prepared = request.prepare()?;
+    let callback_url = authenticate(prepared.url().clone()).await?;
+    prepared.finish(callback_url)
+}
+
+/// Fails if an extra parameter duplicates one of the `reserved` ones set by the request. Providers
+/// might pick either value, and the `state` and code challenge have to be ours.
+fn check_parameters(
+    extra_parameters: &[(String, String)],
+    reserved: &[Option<&str>],
+) -> Result<(), Error> {
+    match extra_parameters
+        .iter()
+        .find(|(key, _)| reserved.contains(&Some(key.as_str())))
+    {
+        Some((key, _)) => Err(Error::ReservedParameter(key.clone())),
+        None => Ok(()),
+    }
+}
+
+fn random_token(length: usize) -> Result<String, Error> {
+    let mut bytes = vec![0u8; length];
+    getrandom::fill(&mut bytes).map_err(Error::Random)?;
+    Ok(URL_SAFE_NO_PAD.encode(bytes))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn request() -> AuthorizationRequest {
+        AuthorizationRequest::new(
+            Url::parse("https://login.example.com/authorize?tenant=1").unwrap(),
+            "client",
+            Url::parse("com.example.app:/callback").unwrap(),
+        )
+        .with_scope("openid")
+        .with_scope("email")
+        .with_issuer("https://login.example.com")
+    }
+
+    fn callback(query: &str) -> Url {
+        Url::parse(&format!("com.example.app:/callback?{query}")).unwrap()
+    }
+
+    #[test]
+    fn pkce_challenge() {
+        // RFC 7636 Appendix B
+        let verifier = PkceVerifier("dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk".to_string());
+        assert_eq!(
+            verifier.challenge(),
+            "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+        );
+    }
+
+    #[test]
+    fn authorization_url() {
+        let prepared = request().prepare().unwrap();
+        let parameters: Vec<_> = prepared.url().query_pairs().into_owned().collect();
+        let get = |key: &str| {
+            parameters
+                .iter()
+                .find(|(name, _)| name == key)
+                .map(|(_, value)| value.as_str())
+        };
+        assert_eq!(get("tenant"), Some("1"));
+        assert_eq!(get("response_type"), Some("code"));
+        assert_eq!(get("client_id"), Some("client"));
+        assert_eq!(get("redirect_uri"), Some("com.example.app:/callback"));
+        assert_eq!(get("scope"), Some("openid email"));
+        assert_eq!(get("state"), Some(prepared.state()));
+        assert_eq!(
+            get("code_challenge"),
+            Some(prepared.pkce_verifier.challenge().as_str())
+        );
+        assert_eq!(get("code_challenge_method"), Some("S256"));
+    }
+
+    #[test]
+    fn fresh_secrets() {
+        let first = request().prepare().unwrap();
+        let second = request().prepare().unwrap();
+        assert_ne!(first.state(), second.state());
+        assert_ne!(first.pkce_verifier.secret(), second.pkce_verifier.secret());
+        // 32 random bytes, as recommended by RFC 7636 §4.1.
+        assert_eq!(first.pkce_verifier.secret().len(), 43);
+    }
+
+    #[test]
+    fn finish() {
+        let prepared = request().prepare().unwrap();
+        let state = prepared.state().to_string();
+        let secret = prepared.pkce_verifier.secret().to_string();
+        let (response, verifier) = prepared
+            .finish(callback(&format!(
+                "code=abc&state={state}&iss=https%3A%2F%2Flogin.example.com"
+            )))
+            .unwrap();
+        assert_eq!(response.code, "abc");
+        assert_eq!(response.state, state);
+        assert_eq!(response.iss.as_deref(), Some("https://login.example.com"));
+        assert_eq!(verifier.secret(), secret);
+    }
+
+    #[test]
+    fn state_mismatch() {
+        let prepared = request().prepare().unwrap();
+        assert!(matches!(
+            prepared.finish(callback("code=abc&state=forged")),
+            Err(Error::StateMismatch)
+        ));
+
+        let prepared = request().prepare().unwrap();
+        assert!(matches!(
+            prepared.finish(callback("code=abc")),
+            Err(Error::MissingResponseParameter("state"))
+        ));
+    }
+
+    #[test]
+    fn issuer() {
+        let prepared = request().prepare().unwrap();
+        let state = prepared.state().to_string();
+        assert!(matches!(
+            prepared.finish(callback(&format!(
+                "code=abc&state={state}&iss=https%3A%2F%2Fevil.example.com"
+            ))),
+            Err(Error::IssuerMismatch(iss)) if iss == "https://evil.example.com"
+        ));
+
+        // Servers not supporting RFC 9207 don't send it at all.
+        let prepared = request().prepare().unwrap();
+        let state = prepared.state().to_string();
+        let (response, _) = prepared
+            .finish(callback(&format!("code=abc&state={state}")))
+            .unwrap();
+        assert_eq!(response.iss, None);
+    }
+
+    #[test]
+    fn reserved_parameters() {
+        for key in [
+            "state",
+            "code_challenge",
+            "redirect_uri",
+            "response_type",
+            "scope",
+        ] {
+            assert!(matches!(
+                request().with_parameter(key, "x").prepare(),
+                Err(Error::ReservedParameter(reserved)) if reserved == key
+            ));
+        }
+        // Only reserved if set.
+        AuthorizationRequest::new(
+            Url::parse("https://login.example.com/authorize").unwrap(),
+            "client",
+            Url::parse("com.example.app:/callback").unwrap(),
+        )
+        .with_parameter("scope", "openid")
+        .prepare()
+        .unwrap();
+    }
+
+    #[test]
+    fn missing_code() {
+        let prepared = request().prepare().unwrap();
+        let state = prepared.state().to_string();
+        assert!(matches!(
+            prepared.finish(callback(&format!("state={state}"))),
+            Err(Error::MissingResponseParameter("code"))
+        ));
+    }
+
+    fn logout_request() -> LogoutRequest {
+        LogoutRequest::new(
+            Url::parse("https://login.example.com/logout").unwrap(),
+            Url::parse("com.example.app:/logged-out").unwrap(),
+        )
+        .with_id_token_hint("token")
+        .with_parameter("ui_locales", "de")
+    }
+
+    #[test]
+    fn logout() {
+        let prepared = logout_request().prepare().unwrap();
+        let parameters: Vec<_> = prepared.url().query_pairs().into_owned().collect();
+        let state = prepared.state().to_string();
+        assert_eq!(
+            parameters,
+            [
+                ("id_token_hint".to_string(), "token".to_string()),
+                (
+                    "post_logout_redirect_uri".to_string(),
+                    "com.example.app:/logged-out".to_string()
+                ),
+                ("state".to_string(), state.clone()),
+                ("ui_locales".to_string(), "de".to_string()),
+            ]
+        );
+        prepared
+            .finish(Url::parse(&format!("com.example.app:/logged-out?state={state}")).unwrap())
+            .unwrap();
+    }
+
+    #[test]
+    fn logout_state_mismatch() {
+        let prepared = logout_request().prepare().unwrap();
+        assert!(matches!(
+            prepared.finish(Url::parse("com.example.app:/logged-out?state=forged").unwrap()),
+            Err(Error::StateMismatch)
+        ));
+
+        let prepared = logout_request().prepare().unwrap();
+        assert!(matches!(
+            prepared.finish(Url::parse("com.example.app:/logged-out").unwrap()),
+            Err(Error::MissingResponseParameter("state"))
+        ));
+    }
+
+    #[test]
+    fn logout_reserved_parameters() {
+        for key in ["state", "post_logout_redirect_uri", "id_token_hint"] {
+            assert!(matches!(
+                logout_request().with_parameter(key, "x").prepare(),
+                Err(Error::ReservedParameter(reserved)) if reserved == key
+            ));
+        }
+        // Only reserved if set.
+        logout_request()
+            .with_parameter("client_id", "client")
+            .prepare()
+            .unwrap();
+    }
+
+    #[test]
+    fn logout_from_discovery_document() {
+        let redirect_uri = Url::parse("com.example.app:/logged-out").unwrap();
+        let request = LogoutRequest::from_discovery_document(
+            r#"{"issuer":"https://login.example.com","end_session_endpoint":"https://login.example.com/logout"}"#,
+            redirect_uri.clone(),
+        )
+        .unwrap();
+        assert_eq!(
+            request.end_session_endpoint.as_str(),
+            "https://login.example.com/logout"
+        );
+        assert_eq!(request.post_logout_redirect_uri, redirect_uri);
+
+        for document in [
+            r#"{"issuer":"https://login.example.com"}"#,
+            r#"{"end_session_endpoint":"/logout"}"#,
+            "not json",
+        ] {
+            assert!(matches!(
+                LogoutRequest::from_discovery_document(document, redirect_uri.clone()),
+                Err(Error::InvalidDiscoveryDocument(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn error_response() {
+        let prepared = request().prepare().unwrap();
+        assert!(matches!(
+            prepared.finish(callback("error=access_denied&state=x")),
+            Err(Error::Authorization {
+                error: crate::AuthorizationErrorCode::AccessDenied,
+                ..
+            })
+        ));
+    }
+}
@@ -0,0 +1,94 @@
+//! Sessions waiting for a callback URL that is delivered from outside of the crate, for example
+//! because the desktop launched the application with the URL as an argument.
+
+use std::{
+    sync::{
+        Mutex,
+        atomic::{AtomicU64, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{CallbackMatcher, Error, timeout::ThreadTimer};
+
+type Callback = Box<dyn FnOnce(Result<url::Url, Error>) + Send>;
+
+struct PendingCallback {
+    id: u64,
+    callback_matcher: CallbackMatcher,
+    callback: Callback,
+}
+
+static PENDING: Mutex<Vec<PendingCallback>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// Registers a session waiting for a URL matched by `callback_matcher`. The session is removed
+/// again when the returned registration is dropped.
+pub(crate) fn register(
+    callback_matcher: CallbackMatcher,
+    callback: impl FnOnce(Result<url::Url, Error>) + Send + 'static,
+) -> Registration {
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    PENDING
+        .lock()
+        .unwrap_or_else(|err| err.into_inner())
+        .push(PendingCallback {
+            id,
+            callback_matcher,
+            callback: Box::new(callback),
+        });
+    Registration { id }
+}
+
+pub(crate) struct Registration {
+    id: u64,
+}
+
+impl Registration {
+    /// Completes the session with [`Error::TimedOut`] if no URL was delivered within `timeout`.
+    /// The timer stops when the returned value is dropped.
+    pub(crate) fn expire_after(&self, timeout: Duration) -> Result<ThreadTimer, Error> {
+        let id = self.id;
+        ThreadTimer::start(timeout, move || {
+            let pending = {
+                let mut pending = PENDING.lock().unwrap_or_else(|err| err.into_inner());
+                let Some(index) = pending.iter().position(|pending| pending.id == id) else {
+                    return;
+                };
+                pending.remove(index)
+            };
+            tracing::debug!("Timed out waiting for callback URL");
+            (pending.callback)(Err(Error::TimedOut));
+        })
+    }
+}
+
+impl Drop for Registration {
+    fn drop(&mut self) {
+        PENDING
+            .lock()
+            .unwrap_or_else(|err| err.into_inner())
+            .retain(|pending| pending.id != self.id);
+    }
+}
+
+/// Hands a callback URL received from outside (command line, scheme handler, another instance) to
+/// the oldest session waiting for it.
+///
+/// Returns `false` if no session was waiting for this URL.
+pub fn deliver_callback_url(url: &url::Url) -> bool {
+    let pending = {
+        let mut pending = PENDING.lock().unwrap_or_else(|err| err.into_inner());
+        let Some(index) = pending
+            .iter()
+            .position(|pending| pending.callback_matcher.matches(url))
+        else {
+            tracing::debug!("No session waiting for callback URL {url}");
+            return false;
+        };
+        pending.remove(index)
+    };
+    // Call outside of the lock, the callback might start another session.
+    (pending.callback)(Ok(url.clone()));
+    true
+}
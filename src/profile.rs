@@ -0,0 +1,402 @@
+use std::path::PathBuf;
+#[cfg(target_os = "windows")]
+use std::{
+    path::Path,
+    sync::atomic::{AtomicU64, Ordering},
+    time::Duration,
+};
+
+use crate::Error;
+
+/// Where the embedded web view keeps cookies, local storage and cache, so the identity provider
+/// remembers the user between sessions. Ignored by `ASWebAuthenticationSession` and the system
+/// browser, which use the browser's own.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub enum Profile {
+    /// The engine's default location for the application, shared by all sessions without a profile.
+    #[default]
+    Shared,
+    /// A directory of its own, created if it doesn't exist. Sessions with different directories
+    /// don't share any data, so every account of the application can have its own.
+    Directory(PathBuf),
+}
+
+impl Profile {
+    /// The profile `name` in the data directory of the application, that is
+    /// `$XDG_DATA_HOME/<app_id>/webauth/<name>` on Linux and
+    /// `%LOCALAPPDATA%\<app_id>\webauth\<name>` on Windows.
+    pub fn named(app_id: &str, name: &str) -> Result<Self, Error> {
+        named_in(data_dir()?, app_id, name)
+    }
+}
+
+fn named_in(data_dir: PathBuf, app_id: &str, name: &str) -> Result<Profile, Error> {
+    if !is_file_name(app_id) {
+        return Err(Error::InvalidAppId(app_id.to_string()));
+    }
+    if !is_file_name(name) {
+        return Err(Error::InvalidProfileName(name.to_string()));
+    }
+    Ok(Profile::Directory(
+        data_dir.join(app_id).join("webauth").join(name),
+    ))
+}
+
+fn is_file_name(name: &str) -> bool {
+    !name.is_empty() && name != "." && name != ".." && !name.contains(['/', '\\', '\0'])
+}
+
+#[cfg(target_os = "linux")]
+fn data_dir() -> Result<PathBuf, Error> {
+    crate::scheme_handler::data_home()
+}
+
+#[cfg(target_os = "windows")]
+fn data_dir() -> Result<PathBuf, Error> {
+    Ok(std::env::var_os("LOCALAPPDATA")
+        .map(PathBuf::from)
+        .ok_or_else(|| {
+            std::io::Error::new(std::io::ErrorKind::NotFound, "LOCALAPPDATA is not set")
+        })?)
+}
+
+#[cfg(not(any(target_os = "linux", target_os = "windows")))]
+fn data_dir() -> Result<PathBuf, Error> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+}
+
+/// How often removing an [`EphemeralDirectory`] is tried. The WebView2 browser process shuts down
+/// on its own time after the web view is gone, and keeps files in the directory open until then.
+#[cfg(target_os = "windows")]
+const REMOVAL_ATTEMPTS: u32 = 20;
+#[cfg(target_os = "windows")]
+const REMOVAL_INTERVAL: Duration = Duration::from_millis(500);
+
+/// A temporary profile directory for ephemeral sessions, deleted when dropped. Only WebView2
+/// needs one, WebKitGTK keeps the data of ephemeral sessions in memory.
+#[cfg(target_os = "windows")]
+pub(crate) struct EphemeralDirectory {
+    path: PathBuf,
+}
+
+#[cfg(target_os = "windows")]
+impl EphemeralDirectory {
+    pub(crate) fn create() -> Result<Self, Error> {
+        static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+        let path = std::env::temp_dir().join(format!(
+            "webauth-{}-{}",
+            std::process::id(),
+            NEXT_ID.fetch_add(1, Ordering::Relaxed)
+        ));
+        // Fails if it already exists, rather than using a directory someone else prepared.
+        std::fs::DirBuilder::new().create(&path)?;
+        Ok(Self { path })
+    }
+
+    pub(crate) fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+#[cfg(target_os = "windows")]
+impl Drop for EphemeralDirectory {
+    fn drop(&mut self) {
+        if std::fs::remove_dir_all(&self.path).is_ok() {
+            return;
+        }
+        // Retried in the background, rather than blocking the thread running the web views.
+        let path = std::mem::take(&mut self.path);
+        let result = std::thread::Builder::new()
+            .name("webauth-cleanup".to_string())
+            .spawn(move || {
+                for attempt in 1.. {
+                    std::thread::sleep(REMOVAL_INTERVAL);
+                    match std::fs::remove_dir_all(&path) {
+                        Ok(()) => return,
+                        Err(err) if err.kind() == std::io::ErrorKind::NotFound => return,
+                        Err(_) if attempt < REMOVAL_ATTEMPTS => {}
+                        Err(err) => {
+                            tracing::warn!(
+                                "Failed removing ephemeral profile {}: {err}",
+                                path.display()
+                            );
+                            return;
+                        }
+                    }
+                }
+            });
+        if let Err(err) = result {
+            tracing::warn!("Failed removing ephemeral profile: {err}");
+        }
+    }
+}
+
+/// The kinds of browsing data to delete with [`clear_browsing_data`].
+#[cfg(not(target_vendor = "apple"))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct BrowsingData {
+    /// Cookies, which usually keep the user signed in to the identity provider.
+    pub cookies: bool,
+    /// Local storage, IndexedDB and other storage of the sites.
+    pub local_storage: bool,
+    /// The HTTP cache and other caches.
+    pub cache: bool,
+}
+
+#[cfg(not(target_vendor = "apple"))]
+impl BrowsingData {
+    pub const ALL: Self = Self {
+        cookies: true,
+        local_storage: true,
+        cache: true,
+    };
+}
+
+/// Deletes browsing data of the embedded web view in `profile`, like the identity provider's
+/// session cookie after the user signed out of the application, so the next session can use
+/// another account.
+///
+/// On Linux, this has to be called on the thread running GTK. On Windows, it has to be called on
+/// the thread running the web views, and fails while a session is using the profile. Not supported
+/// on Android.
+#[cfg(not(target_vendor = "apple"))]
+pub fn clear_browsing_data(
+    profile: &Profile,
+    what: BrowsingData,
+    callback: impl FnOnce(Result<(), Error>) + 'static,
+) {
+    #[cfg(target_os = "linux")]
+    clear_website_data(profile, what, callback);
+    #[cfg(not(target_os = "linux"))]
+    callback(delete_browsing_data(profile, what));
+}
+
+#[cfg(not(target_vendor = "apple"))]
+pub async fn clear_browsing_data_async(profile: &Profile, what: BrowsingData) -> Result<(), Error> {
+    let (sender, receiver) = futures::channel::oneshot::channel();
+
+    clear_browsing_data(profile, what, move |result| {
+        sender.send(result).ok();
+    });
+
+    receiver.await.unwrap_or(Err(Error::Aborted))
+}
+
+#[cfg(target_os = "linux")]
+fn clear_website_data(
+    profile: &Profile,
+    what: BrowsingData,
+    callback: impl FnOnce(Result<(), Error>) + 'static,
+) {
+    use gtk::glib::{self, thread_guard::ThreadGuard};
+    use webkit2gtk::{
+        CookieManagerExt, CookiePersistentStorage, WebContextExt, WebsiteDataManager,
+        WebsiteDataManagerExt, WebsiteDataManagerExtManual, WebsiteDataTypes,
+    };
+
+    let data_manager = match profile {
+        Profile::Shared => webkit2gtk::WebContext::builder()
+            .build()
+            .website_data_manager(),
+        Profile::Directory(directory) => {
+            // Set up like wry does, so the cookies are found in the same place.
+            let data_manager = WebsiteDataManager::builder()
+                .base_data_directory(directory.to_string_lossy())
+                .build();
+            if let Some(cookie_manager) = data_manager.cookie_manager() {
+                cookie_manager.set_persistent_storage(
+                    &directory.join("cookies").to_string_lossy(),
+                    CookiePersistentStorage::Text,
+                );
+            }
+            Some(data_manager)
+        }
+    };
+    let Some(data_manager) = data_manager else {
+        callback(Err(Error::Io(std::io::Error::from(
+            std::io::ErrorKind::Unsupported,
+        ))));
+        return;
+    };
+
+    let mut types = WebsiteDataTypes::empty();
+    if what.cookies {
+        types |= WebsiteDataTypes::COOKIES;
+    }
+    if what.local_storage {
+        types |= WebsiteDataTypes::LOCAL_STORAGE
+            | WebsiteDataTypes::SESSION_STORAGE
+            | WebsiteDataTypes::INDEXEDDB_DATABASES
+            | WebsiteDataTypes::WEBSQL_DATABASES
+            | WebsiteDataTypes::DOM_CACHE
+            | WebsiteDataTypes::SERVICE_WORKER_REGISTRATIONS;
+    }
+    if what.cache {
+        types |= WebsiteDataTypes::MEMORY_CACHE
+            | WebsiteDataTypes::DISK_CACHE
+            | WebsiteDataTypes::OFFLINE_APPLICATION_CACHE;
+    }
+
+    // The result is reported on this thread, the guard only satisfies the bounds.
+    let callback = ThreadGuard::new(callback);
+    data_manager.clear(
+        types,
+        glib::TimeSpan(0),
+        gtk::gio::Cancellable::NONE,
+        move |result| (callback.into_inner())(result.map_err(Error::WebKit)),
+    );
+}
+
+/// Clears the data through a hidden web view of the profile, WebView2 can't do that without one.
+#[cfg(target_os = "windows")]
+fn delete_browsing_data(profile: &Profile, what: BrowsingData) -> Result<(), Error> {
+    use std::sync::mpsc;
+
+    use webview2_com::{
+        ClearBrowsingDataCompletedHandler, CoreWebView2EnvironmentOptions,
+        CreateCoreWebView2ControllerCompletedHandler,
+        CreateCoreWebView2EnvironmentCompletedHandler, Microsoft::Web::WebView2::Win32::*,
+    };
+    use windows::Win32::{
+        Foundation::{E_POINTER, E_UNEXPECTED},
+        UI::WindowsAndMessaging::HWND_MESSAGE,
+    };
+    use windows_core::{HSTRING, Interface, PCWSTR};
+
+    let user_data_folder = match profile {
+        Profile::Directory(directory) => HSTRING::from(directory.as_path()),
+        // Like wry, for the default folder next to the executable.
+        Profile::Shared => HSTRING::new(),
+    };
+    let mut kinds = COREWEBVIEW2_BROWSING_DATA_KINDS(0);
+    if what.cookies {
+        kinds.0 |= COREWEBVIEW2_BROWSING_DATA_KINDS_COOKIES.0;
+    }
+    if what.local_storage {
+        kinds.0 |= COREWEBVIEW2_BROWSING_DATA_KINDS_ALL_DOM_STORAGE.0
+            | COREWEBVIEW2_BROWSING_DATA_KINDS_SERVICE_WORKERS.0;
+    }
+    if what.cache {
+        kinds.0 |= COREWEBVIEW2_BROWSING_DATA_KINDS_DISK_CACHE.0;
+    }
+
+    let (sender, receiver) = mpsc::channel();
+    unsafe {
+        CreateCoreWebView2EnvironmentWithOptions(
+            PCWSTR::null(),
+            &user_data_folder,
+            &ICoreWebView2EnvironmentOptions::from(CoreWebView2EnvironmentOptions::default()),
+            &CreateCoreWebView2EnvironmentCompletedHandler::create(Box::new(
+                move |result, environment| {
+                    result?;
+                    sender
+                        .send(environment.ok_or_else(|| windows_core::Error::from(E_POINTER)))
+                        .map_err(|_| windows_core::Error::from(E_UNEXPECTED))
+                },
+            )),
+        )
+        .map_err(std::io::Error::from)?;
+    }
+    let environment = webview2_com::wait_with_pump(receiver)
+        .map_err(webview2_error)?
+        .map_err(std::io::Error::from)?;
+
+    // A message-only window keeps the web view hidden.
+    let (sender, receiver) = mpsc::channel();
+    unsafe {
+        environment
+            .CreateCoreWebView2Controller(
+                HWND_MESSAGE,
+                &CreateCoreWebView2ControllerCompletedHandler::create(Box::new(
+                    move |result, controller| {
+                        result?;
+                        sender
+                            .send(controller.ok_or_else(|| windows_core::Error::from(E_POINTER)))
+                            .map_err(|_| windows_core::Error::from(E_UNEXPECTED))
+                    },
+                )),
+            )
+            .map_err(std::io::Error::from)?;
+    }
+    let controller = webview2_com::wait_with_pump(receiver)
+        .map_err(webview2_error)?
+        .map_err(std::io::Error::from)?;
+
+    let result = unsafe {
+        controller
+            .CoreWebView2()
+            .and_then(|web_view| web_view.cast::<ICoreWebView2_13>())
+            .and_then(|web_view| web_view.Profile())
+            .and_then(|profile| profile.cast::<ICoreWebView2Profile2>())
+    }
+    .map_err(|err| Error::from(std::io::Error::from(err)))
+    .and_then(|profile| {
+        ClearBrowsingDataCompletedHandler::wait_for_async_operation(
+            Box::new(move |handler| unsafe {
+                profile
+                    .ClearBrowsingData(kinds, &handler)
+                    .map_err(Into::into)
+            }),
+            Box::new(|result| result),
+        )
+        .map_err(webview2_error)
+    });
+    if let Err(err) = unsafe { controller.Close() } {
+        tracing::warn!("Failed closing the web view clearing browsing data: {err}");
+    }
+    result
+}
+
+#[cfg(target_os = "windows")]
+fn webview2_error(err: webview2_com::Error) -> Error {
+    match err {
+        webview2_com::Error::WindowsError(err) => std::io::Error::from(err).into(),
+        err => std::io::Error::other(format!("{err:?}")).into(),
+    }
+}
+
+#[cfg(target_os = "android")]
+fn delete_browsing_data(_profile: &Profile, _what: BrowsingData) -> Result<(), Error> {
+    Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn file_names() {
+        assert!(is_file_name("org.example.App"));
+        assert!(is_file_name("work account"));
+        assert!(is_file_name(".hidden"));
+        assert!(!is_file_name(""));
+        assert!(!is_file_name("."));
+        assert!(!is_file_name(".."));
+        assert!(!is_file_name("work/account"));
+        assert!(!is_file_name("work\\account"));
+        assert!(!is_file_name("work\0account"));
+    }
+
+    #[test]
+    fn named() {
+        let named = |app_id, name| named_in(PathBuf::from("/data"), app_id, name);
+        assert_eq!(
+            named("org.example.App", "work").unwrap(),
+            Profile::Directory(PathBuf::from("/data/org.example.App/webauth/work"))
+        );
+        assert!(matches!(
+            named("../org.example.App", "work"),
+            Err(Error::InvalidAppId(_))
+        ));
+        assert!(matches!(named("", "work"), Err(Error::InvalidAppId(_))));
+        assert!(matches!(
+            named("org.example.App", ".."),
+            Err(Error::InvalidProfileName(_))
+        ));
+        assert!(matches!(
+            named("org.example.App", "a/b"),
+            Err(Error::InvalidProfileName(_))
+        ));
+    }
+}
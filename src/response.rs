@@ -0,0 +1,241 @@
+//! Parsing of the parameters an authorization server attaches to the callback URL.
+
+use std::fmt;
+
+use url::{Url, form_urlencoded};
+
+use crate::Error;
+
+/// The parameters of a callback URL, taken from the query and the fragment (as used by
+/// `response_mode=fragment` and the implicit flow), or from the body of a form post.
+#[derive(Debug, Clone)]
+pub struct CallbackParameters {
+    url: Url,
+    parameters: Vec<(String, String)>,
+}
+
+impl CallbackParameters {
+    /// Parses the parameters of `url`.
+    ///
+    /// Error responses as defined in
+    /// [RFC 6749 §4.1.2.1](https://www.rfc-editor.org/rfc/rfc6749#section-4.1.2.1) are returned
+    /// as [`Error::Authorization`].
+    pub fn from_url(url: Url) -> Result<Self, Error> {
+        Self::from_form_post(url, &[])
+    }
+
+    /// Parses the `application/x-www-form-urlencoded` body of a `response_mode=form_post`
+    /// response together with the parameters of `url`. Parameters in the fragment take
+    /// precedence over those in the query, which take precedence over those in the body.
+    pub fn from_form_post(url: Url, body: &[u8]) -> Result<Self, Error> {
+        let fragment = url.fragment().unwrap_or_default().as_bytes();
+        let parameters: Vec<(String, String)> = form_urlencoded::parse(fragment)
+            .chain(url.query_pairs())
+            .chain(form_urlencoded::parse(body))
+            .map(|(key, value)| (key.into_owned(), value.into_owned()))
+            .collect();
+
+        let this = Self { url, parameters };
+        if let Some(error) = this.get("error") {
+            let Ok(error) = error.parse();
+            return Err(Error::Authorization {
+                error,
+                description: this.get("error_description").map(str::to_string),
+                uri: this.get("error_uri").map(str::to_string),
+                state: this.get("state").map(str::to_string),
+            });
+        }
+        Ok(this)
+    }
+
+    /// The value of the parameter `key`. If it occurs more than once, the first one in order of
+    /// precedence.
+    pub fn get(&self, key: &str) -> Option<&str> {
+        self.parameters
+            .iter()
+            .find(|(name, _)| name == key)
+            .map(|(_, value)| value.as_str())
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = (&str, &str)> {
+        self.parameters
+            .iter()
+            .map(|(key, value)| (key.as_str(), value.as_str()))
+    }
+
+    pub fn url(&self) -> &Url {
+        &self.url
+    }
+
+    pub fn into_url(self) -> Url {
+        self.url
+    }
+}
+
+/// The `error` code of an authorization error response.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[non_exhaustive]
+pub enum AuthorizationErrorCode {
+    /// The resource owner or authorization server denied the request, usually because the user
+    /// cancelled the login.
+    AccessDenied,
+    InvalidRequest,
+    UnauthorizedClient,
+    UnsupportedResponseType,
+    InvalidScope,
+    ServerError,
+    TemporarilyUnavailable,
+    /// Any other code, like the OpenID Connect specific `login_required`.
+    Other(String),
+}
+
+impl AuthorizationErrorCode {
+    pub fn as_str(&self) -> &str {
+        match self {
+            Self::AccessDenied => "access_denied",
+            Self::InvalidRequest => "invalid_request",
+            Self::UnauthorizedClient => "unauthorized_client",
+            Self::UnsupportedResponseType => "unsupported_response_type",
+            Self::InvalidScope => "invalid_scope",
+            Self::ServerError => "server_error",
+            Self::TemporarilyUnavailable => "temporarily_unavailable",
+            Self::Other(code) => code,
+        }
+    }
+}
+
+impl std::str::FromStr for AuthorizationErrorCode {
+    type Err = std::convert::Infallible;
+
+    fn from_str(code: &str) -> Result<Self, Self::Err> {
+        Ok(match code {
+            "access_denied" => Self::AccessDenied,
+            "invalid_request" => Self::InvalidRequest,
+            "unauthorized_client" => Self::UnauthorizedClient,
+            "unsupported_response_type" => Self::UnsupportedResponseType,
+            "invalid_scope" => Self::InvalidScope,
+            "server_error" => Self::ServerError,
+            "temporarily_unavailable" => Self::TemporarilyUnavailable,
+            code => Self::Other(code.to_string()),
+        })
+    }
+}
+
+impl fmt::Display for AuthorizationErrorCode {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.write_str(self.as_str())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parameters() {
+        let parameters = CallbackParameters::from_url(
+            Url::parse("com.example.app:/callback?code=a%2Bb&state=xyz").unwrap(),
+        )
+        .unwrap();
+        assert_eq!(parameters.get("code"), Some("a+b"));
+        assert_eq!(parameters.get("state"), Some("xyz"));
+        assert_eq!(parameters.get("iss"), None);
+        assert_eq!(
+            parameters.iter().collect::<Vec<_>>(),
+            [("code", "a+b"), ("state", "xyz")]
+        );
+    }
+
+    #[test]
+    fn precedence() {
+        let parameters = CallbackParameters::from_form_post(
+            Url::parse("https://example.com/callback?a=query&b=query#a=fragment").unwrap(),
+            b"a=body&b=body&c=body",
+        )
+        .unwrap();
+        assert_eq!(parameters.get("a"), Some("fragment"));
+        assert_eq!(parameters.get("b"), Some("query"));
+        assert_eq!(parameters.get("c"), Some("body"));
+    }
+
+    #[test]
+    fn error_response() {
+        let result = CallbackParameters::from_url(
+            Url::parse(
+                "com.example.app:/callback?error=access_denied\
+                 &error_description=The+user+cancelled&error_uri=https%3A%2F%2Fexample.com%2Fe\
+                 &state=xyz",
+            )
+            .unwrap(),
+        );
+        let Err(Error::Authorization {
+            error,
+            description,
+            uri,
+            state,
+        }) = result
+        else {
+            panic!("expected an authorization error, got {result:?}");
+        };
+        assert_eq!(error, AuthorizationErrorCode::AccessDenied);
+        assert_eq!(description.as_deref(), Some("The user cancelled"));
+        assert_eq!(uri.as_deref(), Some("https://example.com/e"));
+        assert_eq!(state.as_deref(), Some("xyz"));
+    }
+
+    #[test]
+    fn error_response_in_fragment_and_body() {
+        let result = CallbackParameters::from_url(
+            Url::parse("https://example.com/callback#error=server_error").unwrap(),
+        );
+        assert!(matches!(
+            result,
+            Err(Error::Authorization {
+                error: AuthorizationErrorCode::ServerError,
+                description: None,
+                uri: None,
+                state: None,
+            })
+        ));
+
+        let result = CallbackParameters::from_form_post(
+            Url::parse("https://example.com/callback").unwrap(),
+            b"error=invalid_scope&state=xyz",
+        );
+        assert!(matches!(
+            result,
+            Err(Error::Authorization {
+                error: AuthorizationErrorCode::InvalidScope,
+                ..
+            })
+        ));
+    }
+
+    #[test]
+    fn error_codes() {
+        for code in [
+            "access_denied",
+            "invalid_request",
+            "unauthorized_client",
+            "unsupported_response_type",
+            "invalid_scope",
+            "server_error",
+            "temporarily_unavailable",
+        ] {
+            let Ok(parsed) = code.parse::<AuthorizationErrorCode>();
+            assert!(
+                !matches!(parsed, AuthorizationErrorCode::Other(_)),
+                "{code}"
+            );
+            assert_eq!(parsed.as_str(), code);
+            assert_eq!(parsed.to_string(), code);
+        }
+
+        let Ok(parsed) = "login_required".parse::<AuthorizationErrorCode>();
+        assert_eq!(
+            parsed,
+            AuthorizationErrorCode::Other("login_required".to_string())
+        );
+        assert_eq!(parsed.as_str(), "login_required");
+    }
+}
@@ -0,0 +1,142 @@
+//! SAML 2.0 Web Browser SSO with the HTTP-POST binding for the response
+//! ([SAML Bindings §3.5](https://docs.oasis-open.org/security/saml/v2.0/saml-bindings-2.0-os.pdf)).
+//!
+//! The identity provider answers with an auto-submitting form that POSTs the `SAMLResponse` to the
+//! assertion consumer service (ACS) URL. The embedded webview intercepts that form submission, so
+//! the ACS URL doesn't have to be reachable, and returns the response to the application. Only
+//! forms submitted from pages with the origin of the authentication URL are accepted, so other
+//! sites loaded in the webview can't hand in responses of their own. Validating the assertion is
+//! up to the caller.
+
+use base64::{Engine, engine::general_purpose::STANDARD};
+use raw_window_handle::HasWindowHandle;
+use url::form_urlencoded;
+
+use crate::{
+    AuthSession, CallbackMatcher, CancelToken, Error,
+    webview::{self, Capture, Captured},
+};
+
+/// The form values posted to the ACS URL.
+#[derive(Debug, Clone)]
+pub struct SamlResponse {
+    /// The URL the form was posted to.
+    pub acs_url: url::Url,
+    /// The `SAMLResponse` value as posted, base64 encoded.
+    pub saml_response: String,
+    /// The decoded `SAMLResponse`, an XML document.
+    pub xml: Vec<u8>,
+    pub relay_state: Option<String>,
+}
+
+/// Opens `auth_url` (usually carrying a `SAMLRequest` for the HTTP-Redirect binding) and waits for
+/// the identity provider to post the response to `acs_url`.
+pub fn authenticate(
+    auth_url: &url::Url,
+    acs_url: &url::Url,
+    options: crate::WebAuthOptions,
+    window: &impl HasWindowHandle,
+    callback: impl FnOnce(Result<SamlResponse, Error>) + 'static,
+) -> Result<CancelToken, Error> {
+    let session = AuthSession::builder()
+        .url(auth_url.clone())
+        .callback_matcher(CallbackMatcher::RedirectUri(acs_url.clone()))
+        .options(options)
+        .parent(window)
+        .build()?;
+    webview::start(
+        session,
+        Capture::FormSubmission(acs_url.clone()),
+        move |result| callback(result.and_then(parse)),
+    )
+}
+
+pub async fn authenticate_async(
+    auth_url: &url::Url,
+    acs_url: &url::Url,
+    options: crate::WebAuthOptions,
+    window: &impl HasWindowHandle,
+) -> Result<SamlResponse, Error> {
+    let (sender, receiver) = futures::channel::oneshot::channel();
+
+    let cancel_token = authenticate(auth_url, acs_url, options, window, move |result| {
+        sender.send(result).ok();
+    })?;
+
+    let result = receiver.await.unwrap_or(Err(crate::Error::Aborted));
+    drop(cancel_token);
+
+    result
+}
+
+fn parse(captured: Captured) -> Result<SamlResponse, Error> {
+    let mut saml_response = None;
+    let mut relay_state = None;
+    for (key, value) in form_urlencoded::parse(&captured.body) {
+        match key.as_ref() {
+            "SAMLResponse" => saml_response = Some(value.into_owned()),
+            "RelayState" => relay_state = Some(value.into_owned()),
+            _ => {}
+        }
+    }
+    let saml_response = saml_response.ok_or(Error::MissingResponseParameter("SAMLResponse"))?;
+
+    // The value may be wrapped into lines.
+    let encoded: Vec<u8> = saml_response
+        .bytes()
+        .filter(|byte| !byte.is_ascii_whitespace())
+        .collect();
+    let xml = STANDARD
+        .decode(encoded)
+        .map_err(Error::InvalidSamlResponse)?;
+
+    Ok(SamlResponse {
+        acs_url: captured.url,
+        saml_response,
+        xml,
+        relay_state,
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn captured(body: &str) -> Captured {
+        Captured {
+            url: url::Url::parse("https://sp.example.com/saml/acs").unwrap(),
+            body: body.as_bytes().to_vec(),
+        }
+    }
+
+    #[test]
+    fn response() {
+        let response = parse(captured(
+            "SAMLResponse=PHNhbWxwOlJlc3BvbnNlLz4%3D&RelayState=%2Fhome&other=1",
+        ))
+        .unwrap();
+        assert_eq!(response.acs_url.as_str(), "https://sp.example.com/saml/acs");
+        assert_eq!(response.saml_response, "PHNhbWxwOlJlc3BvbnNlLz4=");
+        assert_eq!(response.xml, b"<samlp:Response/>");
+        assert_eq!(response.relay_state.as_deref(), Some("/home"));
+    }
+
+    #[test]
+    fn wrapped_response() {
+        let response = parse(captured("SAMLResponse=PHNhbWxwOlJl%0D%0Ac3BvbnNlLz4%3D")).unwrap();
+        assert_eq!(response.xml, b"<samlp:Response/>");
+        assert_eq!(response.relay_state, None);
+    }
+
+    #[test]
+    fn invalid_response() {
+        assert!(matches!(
+            parse(captured("RelayState=%2Fhome")),
+            Err(Error::MissingResponseParameter("SAMLResponse"))
+        ));
+        assert!(matches!(
+            parse(captured("SAMLResponse=not*base64")),
+            Err(Error::InvalidSamlResponse(_))
+        ));
+    }
+}
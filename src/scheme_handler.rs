@@ -0,0 +1,510 @@
+//! Registration of the application as the handler for a private-use URL scheme on Linux desktops.
+//!
+//! This writes a [desktop entry](https://specifications.freedesktop.org/desktop-entry-spec/latest/)
+//! to `$XDG_DATA_HOME/applications` announcing `x-scheme-handler/<scheme>` and makes it the default
+//! for that scheme in `$XDG_CONFIG_HOME/mimeapps.list`, just like `xdg-mime default` would.
+
+use std::{
+    io::ErrorKind,
+    path::{Path, PathBuf},
+    process::Command,
+};
+
+use crate::Error;
+
+const DESKTOP_ENTRY_GROUP: &str = "Desktop Entry";
+const DEFAULT_APPLICATIONS_GROUP: &str = "Default Applications";
+const GENERATED_MARKER: &str = "# Generated by webauth";
+
+/// Registers the running executable as handler for `scheme`.
+///
+/// `app_id` is the desktop file ID without the `.desktop` suffix (usually the reverse-DNS
+/// application ID), `app_name` the human-readable name shown in dialogs. If a desktop entry with
+/// this ID was written by an earlier registration, the scheme is added to its `MimeType` list and
+/// its `Exec` line is updated to point at the current executable. Desktop entries installed by
+/// anyone else are left untouched, only the default application for the scheme is set to them.
+///
+/// Returns the path of the desktop entry.
+pub fn register(scheme: &str, app_id: &str, app_name: &str) -> Result<PathBuf, Error> {
+    register_with_executable(scheme, app_id, app_name, &std::env::current_exe()?)
+}
+
+/// Like [`register`], but launches `executable` instead of the running one.
+pub fn register_with_executable(
+    scheme: &str,
+    app_id: &str,
+    app_name: &str,
+    executable: &Path,
+) -> Result<PathBuf, Error> {
+    register_in(&XdgDirs::from_env()?, scheme, app_id, app_name, executable)
+}
+
+fn register_in(
+    dirs: &XdgDirs,
+    scheme: &str,
+    app_id: &str,
+    app_name: &str,
+    executable: &Path,
+) -> Result<PathBuf, Error> {
+    let mime_type = mime_type(scheme)?;
+    let desktop_file_id = desktop_file_id(app_id)?;
+    let applications_dir = dirs.data_home.join("applications");
+    let desktop_file = applications_dir.join(&desktop_file_id);
+
+    let contents = match std::fs::read_to_string(&desktop_file) {
+        Ok(contents) => Some(contents).filter(|contents| contents.starts_with(GENERATED_MARKER)),
+        Err(err) if err.kind() == ErrorKind::NotFound => Some(format!(
+            "{GENERATED_MARKER}\n[{DESKTOP_ENTRY_GROUP}]\nType=Application\nName={}\nTerminal=false\nNoDisplay=true\n",
+            escape_value(app_name)
+        )),
+        Err(err) => return Err(err.into()),
+    };
+    match contents {
+        Some(contents) => {
+            let contents = update_key(&contents, DESKTOP_ENTRY_GROUP, "Exec", |_| {
+                Some(format!(
+                    "{} %u",
+                    escape_value(&quote_exec_argument(&executable.to_string_lossy()))
+                ))
+            });
+            let contents = update_key(&contents, DESKTOP_ENTRY_GROUP, "MimeType", |value| {
+                let mut mime_types = split_list(value.unwrap_or_default());
+                if !mime_types.contains(&mime_type.as_str()) {
+                    mime_types.push(&mime_type);
+                }
+                Some(join_list(&mime_types))
+            });
+            write_atomically(&desktop_file, &contents)?;
+            tracing::debug!("Wrote desktop entry {}", desktop_file.display());
+        }
+        None => tracing::debug!("Keeping foreign desktop entry {}", desktop_file.display()),
+    }
+
+    let mimeapps = dirs.mimeapps_list();
+    let contents = read_to_string_or_empty(&mimeapps)?;
+    let contents = update_key(&contents, DEFAULT_APPLICATIONS_GROUP, &mime_type, |_| {
+        Some(desktop_file_id.clone())
+    });
+    write_atomically(&mimeapps, &contents)?;
+
+    update_desktop_database(&applications_dir);
+
+    Ok(desktop_file)
+}
+
+/// Reverts [`register`].
+///
+/// The scheme is removed from the desktop entry's `MimeType` list and from the default
+/// applications, if it still points to `app_id`. A desktop entry created by [`register`] is deleted
+/// once it doesn't handle any scheme anymore.
+pub fn unregister(scheme: &str, app_id: &str) -> Result<(), Error> {
+    unregister_in(&XdgDirs::from_env()?, scheme, app_id)
+}
+
+fn unregister_in(dirs: &XdgDirs, scheme: &str, app_id: &str) -> Result<(), Error> {
+    let mime_type = mime_type(scheme)?;
+    let desktop_file_id = desktop_file_id(app_id)?;
+    let applications_dir = dirs.data_home.join("applications");
+    let desktop_file = applications_dir.join(&desktop_file_id);
+
+    let contents = read_to_string_or_empty(&desktop_file)?;
+    if contents.starts_with(GENERATED_MARKER) {
+        let mut remaining = 0;
+        let contents = update_key(&contents, DESKTOP_ENTRY_GROUP, "MimeType", |value| {
+            let mut mime_types = split_list(value?);
+            mime_types.retain(|existing| *existing != mime_type);
+            remaining = mime_types.len();
+            (!mime_types.is_empty()).then(|| join_list(&mime_types))
+        });
+        if remaining == 0 {
+            std::fs::remove_file(&desktop_file)?;
+            tracing::debug!("Removed desktop entry {}", desktop_file.display());
+        } else {
+            write_atomically(&desktop_file, &contents)?;
+        }
+    }
+
+    let mimeapps = dirs.mimeapps_list();
+    let contents = read_to_string_or_empty(&mimeapps)?;
+    if !contents.is_empty() {
+        let contents = update_key(&contents, DEFAULT_APPLICATIONS_GROUP, &mime_type, |value| {
+            let value = value?;
+            let handlers: Vec<_> = split_list(value)
+                .into_iter()
+                .filter(|handler| *handler != desktop_file_id)
+                .collect();
+            (!handlers.is_empty()).then(|| join_list(&handlers))
+        });
+        write_atomically(&mimeapps, &contents)?;
+    }
+
+    update_desktop_database(&applications_dir);
+
+    Ok(())
+}
+
+fn mime_type(scheme: &str) -> Result<String, Error> {
+    // RFC 3986 §3.1: ALPHA *( ALPHA / DIGIT / "+" / "-" / "." )
+    let mut chars = scheme.chars();
+    let valid = chars.next().is_some_and(|c| c.is_ascii_alphabetic())
+        && chars.all(|c| c.is_ascii_alphanumeric() || matches!(c, '+' | '-' | '.'));
+    if valid {
+        Ok(format!("x-scheme-handler/{}", scheme.to_ascii_lowercase()))
+    } else {
+        Err(Error::InvalidScheme(scheme.to_string()))
+    }
+}
+
+fn desktop_file_id(app_id: &str) -> Result<String, Error> {
+    if app_id.is_empty() || app_id.contains(['/', '\0']) {
+        Err(Error::InvalidAppId(app_id.to_string()))
+    } else {
+        Ok(format!("{app_id}.desktop"))
+    }
+}
+
+fn xdg_dir(variable: &str, fallback: &str) -> Result<PathBuf, Error> {
+    if let Some(dir) = std::env::var_os(variable).map(PathBuf::from)
+        && dir.is_absolute()
+    {
+        return Ok(dir);
+    }
+    let home = std::env::var_os("HOME")
+        .ok_or_else(|| std::io::Error::new(ErrorKind::NotFound, "HOME is not set"))?;
+    Ok(PathBuf::from(home).join(fallback))
+}
+
+pub(crate) fn data_home() -> Result<PathBuf, Error> {
+    xdg_dir("XDG_DATA_HOME", ".local/share")
+}
+
+/// The base directories registrations are written to.
+struct XdgDirs {
+    data_home: PathBuf,
+    config_home: PathBuf,
+}
+
+impl XdgDirs {
+    fn from_env() -> Result<Self, Error> {
+        Ok(Self {
+            data_home: data_home()?,
+            config_home: xdg_dir("XDG_CONFIG_HOME", ".config")?,
+        })
+    }
+
+    fn mimeapps_list(&self) -> PathBuf {
+        self.config_home.join("mimeapps.list")
+    }
+}
+
+fn read_to_string_or_empty(path: &Path) -> Result<String, Error> {
+    match std::fs::read_to_string(path) {
+        Ok(contents) => Ok(contents),
+        Err(err) if err.kind() == ErrorKind::NotFound => Ok(String::new()),
+        Err(err) => Err(err.into()),
+    }
+}
+
+fn write_atomically(path: &Path, contents: &str) -> Result<(), Error> {
+    if let Some(parent) = path.parent() {
+        std::fs::create_dir_all(parent)?;
+    }
+    let mut temporary = path.as_os_str().to_owned();
+    temporary.push(".webauth-tmp");
+    std::fs::write(&temporary, contents)?;
+    std::fs::rename(&temporary, path)?;
+    Ok(())
+}
+
+/// Sets, replaces or removes (if `update` returns `None`) a key in a group of a desktop-entry-style
+/// key file, creating the group if necessary. Everything else is left untouched.
+fn update_key(
+    contents: &str,
+    group: &str,
+    key: &str,
+    update: impl FnOnce(Option<&str>) -> Option<String>,
+) -> String {
+    let lines: Vec<&str> = contents.lines().collect();
+    let group_header = format!("[{group}]");
+    let group_start = lines.iter().position(|line| line.trim() == group_header);
+
+    let mut result: Vec<String> = lines.iter().map(|line| line.to_string()).collect();
+    match group_start {
+        Some(start) => {
+            let end = lines[start + 1..]
+                .iter()
+                .position(|line| line.trim_start().starts_with('['))
+                .map_or(lines.len(), |offset| start + 1 + offset);
+            let existing = (start + 1..end).find(|&index| {
+                lines[index]
+                    .split_once('=')
+                    .is_some_and(|(name, _)| name.trim() == key)
+            });
+            match existing {
+                Some(index) => {
+                    let value = lines[index].split_once('=').map(|(_, value)| value.trim());
+                    match update(value) {
+                        Some(value) => result[index] = format!("{key}={value}"),
+                        None => {
+                            result.remove(index);
+                        }
+                    }
+                }
+                None => {
+                    if let Some(value) = update(None) {
+                        // Insert after the last non-empty line of the group.
+                        let insert_at = (start + 1..end)
+                            .rev()
+                            .find(|&index| !lines[index].trim().is_empty())
+                            .map_or(start + 1, |index| index + 1);
+                        result.insert(insert_at, format!("{key}={value}"));
+                    }
+                }
+            }
+        }
+        None => {
+            if let Some(value) = update(None) {
+                if result.last().is_some_and(|line| !line.trim().is_empty()) {
+                    result.push(String::new());
+                }
+                result.push(group_header);
+                result.push(format!("{key}={value}"));
+            }
+        }
+    }
+
+    let mut contents = result.join("\n");
+    contents.push('\n');
+    contents
+}
+
+fn split_list(value: &str) -> Vec<&str> {
+    value
+        .split(';')
+        .map(str::trim)
+        .filter(|item| !item.is_empty())
+        .collect()
+}
+
+fn join_list(items: &[&str]) -> String {
+    let mut value = items.join(";");
+    value.push(';');
+    value
+}
+
+/// Escapes a string value according to the desktop entry specification.
+fn escape_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\n', "\\n")
+        .replace('\t', "\\t")
+        .replace('\r', "\\r")
+}
+
+/// Quotes an argument of the `Exec` key, before escaping it as a string value.
+fn quote_exec_argument(argument: &str) -> String {
+    let argument = argument.replace('%', "%%");
+    let reserved = [
+        ' ', '\t', '\n', '"', '\'', '\\', '>', '<', '~', '|', '&', ';', '$', '*', '?', '#', '(',
+        ')', '`',
+    ];
+    if argument.contains(reserved) {
+        let mut quoted = String::with_capacity(argument.len() + 2);
+        quoted.push('"');
+        for c in argument.chars() {
+            if matches!(c, '"' | '`' | '$' | '\\') {
+                quoted.push('\\');
+            }
+            quoted.push(c);
+        }
+        quoted.push('"');
+        quoted
+    } else {
+        argument
+    }
+}
+
+fn update_desktop_database(applications_dir: &Path) {
+    match Command::new("update-desktop-database")
+        .arg(applications_dir)
+        .status()
+    {
+        Ok(status) if !status.success() => {
+            tracing::debug!("update-desktop-database failed with {status}");
+        }
+        Ok(_) => {}
+        Err(err) => tracing::debug!("Could not run update-desktop-database: {err}"),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Runs `test` with XDG base directories in a fresh temporary directory. They're passed
+    /// explicitly, as other threads of the test binary read the environment.
+    fn with_xdg_dirs(name: &str, test: impl FnOnce(&XdgDirs)) {
+        let root = std::env::temp_dir().join(format!("webauth-test-{}-{name}", std::process::id()));
+        std::fs::create_dir_all(&root).unwrap();
+        let dirs = XdgDirs {
+            data_home: root.join("data"),
+            config_home: root.join("config"),
+        };
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| test(&dirs)));
+        std::fs::remove_dir_all(&root).ok();
+        if let Err(panic) = result {
+            std::panic::resume_unwind(panic);
+        }
+    }
+
+    #[test]
+    fn register_and_unregister() {
+        with_xdg_dirs("register", |dirs| {
+            let desktop_file = dirs.data_home.join("applications/org.example.App.desktop");
+            let mimeapps = dirs.mimeapps_list();
+
+            let path = register_in(
+                dirs,
+                "org.example.app",
+                "org.example.App",
+                "Example",
+                Path::new("/opt/example app/bin/example"),
+            )
+            .unwrap();
+            assert_eq!(path, desktop_file);
+            assert_eq!(
+                std::fs::read_to_string(&desktop_file).unwrap(),
+                "# Generated by webauth\n\
+                 [Desktop Entry]\n\
+                 Type=Application\n\
+                 Name=Example\n\
+                 Terminal=false\n\
+                 NoDisplay=true\n\
+                 Exec=\"/opt/example app/bin/example\" %u\n\
+                 MimeType=x-scheme-handler/org.example.app;\n"
+            );
+            assert_eq!(
+                std::fs::read_to_string(&mimeapps).unwrap(),
+                "[Default Applications]\n\
+                 x-scheme-handler/org.example.app=org.example.App.desktop\n"
+            );
+
+            register_in(
+                dirs,
+                "Org.Example.Other",
+                "org.example.App",
+                "Example",
+                Path::new("/usr/bin/example"),
+            )
+            .unwrap();
+            let contents = std::fs::read_to_string(&desktop_file).unwrap();
+            assert!(contents.contains("Exec=/usr/bin/example %u\n"));
+            assert!(contents.contains(
+                "MimeType=x-scheme-handler/org.example.app;x-scheme-handler/org.example.other;\n"
+            ));
+            assert!(
+                std::fs::read_to_string(&mimeapps)
+                    .unwrap()
+                    .contains("x-scheme-handler/org.example.other=org.example.App.desktop\n")
+            );
+
+            unregister_in(dirs, "org.example.app", "org.example.App").unwrap();
+            assert!(
+                std::fs::read_to_string(&desktop_file)
+                    .unwrap()
+                    .contains("MimeType=x-scheme-handler/org.example.other;\n")
+            );
+            assert_eq!(
+                std::fs::read_to_string(&mimeapps).unwrap(),
+                "[Default Applications]\n\
+                 x-scheme-handler/org.example.other=org.example.App.desktop\n"
+            );
+
+            unregister_in(dirs, "org.example.other", "org.example.App").unwrap();
+            assert!(!desktop_file.exists());
+            assert_eq!(
+                std::fs::read_to_string(&mimeapps).unwrap(),
+                "[Default Applications]\n"
+            );
+        });
+    }
+
+    #[test]
+    fn foreign_entries() {
+        with_xdg_dirs("foreign", |dirs| {
+            let desktop_file = dirs.data_home.join("applications/org.example.App.desktop");
+            let mimeapps = dirs.mimeapps_list();
+            std::fs::create_dir_all(desktop_file.parent().unwrap()).unwrap();
+            std::fs::create_dir_all(&dirs.config_home).unwrap();
+            std::fs::write(
+                &desktop_file,
+                "[Desktop Entry]\nType=Application\nName=Example\nExec=example %U\n",
+            )
+            .unwrap();
+            std::fs::write(
+                &mimeapps,
+                "[Added Associations]\ntext/html=browser.desktop;\n\n\
+                 [Default Applications]\ntext/html=browser.desktop\n",
+            )
+            .unwrap();
+
+            register_in(
+                dirs,
+                "org.example.app",
+                "org.example.App",
+                "Ignored",
+                Path::new("/usr/bin/example"),
+            )
+            .unwrap();
+            assert_eq!(
+                std::fs::read_to_string(&desktop_file).unwrap(),
+                "[Desktop Entry]\nType=Application\nName=Example\nExec=example %U\n"
+            );
+            assert!(
+                std::fs::read_to_string(&mimeapps)
+                    .unwrap()
+                    .contains("x-scheme-handler/org.example.app=org.example.App.desktop\n")
+            );
+
+            unregister_in(dirs, "org.example.app", "org.example.App").unwrap();
+            assert_eq!(
+                std::fs::read_to_string(&desktop_file).unwrap(),
+                "[Desktop Entry]\nType=Application\nName=Example\nExec=example %U\n"
+            );
+            assert_eq!(
+                std::fs::read_to_string(&mimeapps).unwrap(),
+                "[Added Associations]\ntext/html=browser.desktop;\n\n\
+                 [Default Applications]\ntext/html=browser.desktop\n"
+            );
+        });
+    }
+
+    #[test]
+    fn invalid_names() {
+        assert!(matches!(
+            mime_type("1password"),
+            Err(Error::InvalidScheme(_))
+        ));
+        assert!(matches!(
+            mime_type("com_example"),
+            Err(Error::InvalidScheme(_))
+        ));
+        assert!(matches!(mime_type(""), Err(Error::InvalidScheme(_))));
+        assert!(matches!(
+            desktop_file_id("../org.example.App"),
+            Err(Error::InvalidAppId(_))
+        ));
+        assert!(matches!(desktop_file_id(""), Err(Error::InvalidAppId(_))));
+    }
+
+    #[test]
+    fn exec_quoting() {
+        assert_eq!(quote_exec_argument("/usr/bin/example"), "/usr/bin/example");
+        assert_eq!(quote_exec_argument("/tmp/100%"), "/tmp/100%%");
+        assert_eq!(
+            quote_exec_argument("/home/me/My $App"),
+            "\"/home/me/My \\$App\""
+        );
+        assert_eq!(escape_value("a\\b\nc"), "a\\\\b\\nc");
+    }
+}
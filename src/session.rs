@@ -0,0 +1,314 @@
+//! The parameters of an authentication session, collected by [`AuthSessionBuilder`].
+
+use std::time::Duration;
+
+#[cfg(target_os = "linux")]
+use gtk::{Container, glib::IsA};
+use raw_window_handle::HasWindowHandle;
+use url::Url;
+
+#[cfg(target_os = "linux")]
+use crate::window::WindowOptions;
+use crate::{
+    AuthEvent, CallbackMatcher, CancelToken, Error, NavigationPolicy, NewWindowPolicy, Profile,
+    UserAgent, WebAuthOptions,
+};
+
+/// An authentication session, ready to be started.
+///
+/// ```ignore
+/// let url = webauth::AuthSession::builder()
+///     .url(auth_url)
+///     .callback_matcher("com.example.app")
+///     .parent(&window)
+///     .timeout(Duration::from_secs(300))
+///     .start_async()
+///     .await?;
+/// ```
+pub struct AuthSession<'a> {
+    pub(crate) auth_url: Url,
+    pub(crate) callback_matcher: CallbackMatcher,
+    pub(crate) options: WebAuthOptions,
+    pub(crate) parent: Parent<'a>,
+    pub(crate) events: Events,
+    /// The window opened when the web view can't be embedded into a [`Parent::Window`].
+    #[cfg(target_os = "linux")]
+    pub(crate) window_options: WindowOptions,
+    #[cfg(target_os = "linux")]
+    pub(crate) transient_for: Option<gtk::Window>,
+}
+
+impl<'a> AuthSession<'a> {
+    pub fn builder() -> AuthSessionBuilder<'a> {
+        AuthSessionBuilder::default()
+    }
+
+    /// Opens `auth_url` and calls `callback` with the first URL matched by the callback matcher,
+    /// or with the error that ended the session.
+    pub fn start(
+        self,
+        callback: impl FnOnce(Result<Url, Error>) + 'static,
+    ) -> Result<CancelToken, Error> {
+        #[cfg(target_vendor = "apple")]
+        {
+            crate::darwin::start(self, callback)
+        }
+        #[cfg(not(target_vendor = "apple"))]
+        {
+            crate::webview::start(self, crate::webview::Capture::Navigation, move |result| {
+                callback(result.map(|captured| captured.url))
+            })
+        }
+    }
+
+    pub async fn start_async(self) -> Result<Url, Error> {
+        let (sender, receiver) = futures::channel::oneshot::channel();
+
+        let cancel_token = self.start(move |result| {
+            sender.send(result).ok();
+        })?;
+
+        let result = receiver.await.unwrap_or(Err(crate::Error::Aborted));
+        drop(cancel_token);
+
+        result
+    }
+}
+
+/// Where the session is shown.
+pub(crate) enum Parent<'a> {
+    Window(&'a dyn HasWindowHandle),
+    #[cfg(target_os = "linux")]
+    Container(Container),
+    #[cfg(target_os = "linux")]
+    ManagedWindow {
+        window_options: WindowOptions,
+        transient_for: Option<gtk::Window>,
+    },
+}
+
+/// Receives the [`AuthEvent`]s of a session.
+#[derive(Default)]
+pub(crate) struct Events {
+    handler: Option<EventHandler>,
+}
+
+type EventHandler = Box<dyn Fn(AuthEvent<'_>)>;
+
+impl Events {
+    /// Adds a handler, called after those already set.
+    pub(crate) fn add(&mut self, handler: impl Fn(AuthEvent<'_>) + 'static) {
+        self.handler = Some(match self.handler.take() {
+            Some(previous) => Box::new(move |event| {
+                previous(event);
+                handler(event);
+            }),
+            None => Box::new(handler),
+        });
+    }
+
+    pub(crate) fn emit(&self, event: AuthEvent<'_>) {
+        if let Some(handler) = &self.handler {
+            handler(event);
+        }
+    }
+}
+
+/// Collects the parameters of an [`AuthSession`]. The URL and callback matcher are required, and
+/// so is the parent, except on Linux, where a window of its own is opened by default.
+#[derive(Default)]
+pub struct AuthSessionBuilder<'a> {
+    auth_url: Option<Url>,
+    callback_matcher: Option<CallbackMatcher>,
+    options: WebAuthOptions,
+    parent: Option<Parent<'a>>,
+    events: Events,
+    #[cfg(target_os = "linux")]
+    window_options: WindowOptions,
+    #[cfg(target_os = "linux")]
+    transient_for: Option<gtk::Window>,
+}
+
+impl<'a> AuthSessionBuilder<'a> {
+    pub fn url(mut self, auth_url: Url) -> Self {
+        self.auth_url = Some(auth_url);
+        self
+    }
+
+    pub fn callback_matcher(mut self, callback_matcher: impl Into<CallbackMatcher>) -> Self {
+        self.callback_matcher = Some(callback_matcher.into());
+        self
+    }
+
+    /// Replaces all options set so far.
+    pub fn options(mut self, options: WebAuthOptions) -> Self {
+        self.options = options;
+        self
+    }
+
+    pub fn prefers_ephemeral_web_browser_session(mut self, ephemeral: bool) -> Self {
+        self.options.prefers_ephemeral_web_browser_session = ephemeral;
+        self
+    }
+
+    /// Adds a header field to the initial request.
+    pub fn header(mut self, name: impl Into<String>, value: impl Into<String>) -> Self {
+        self.options
+            .additional_header_fields
+            .insert(name.into(), value.into());
+        self
+    }
+
+    pub fn timeout(mut self, timeout: Duration) -> Self {
+        self.options.timeout = Some(timeout);
+        self
+    }
+
+    pub fn idle_timeout(mut self, idle_timeout: Duration) -> Self {
+        self.options.idle_timeout = Some(idle_timeout);
+        self
+    }
+
+    pub fn navigation_policy(mut self, navigation_policy: NavigationPolicy) -> Self {
+        self.options.navigation_policy = navigation_policy;
+        self
+    }
+
+    pub fn new_window_policy(mut self, new_window_policy: NewWindowPolicy) -> Self {
+        self.options.new_window_policy = new_window_policy;
+        self
+    }
+
+    pub fn profile(mut self, profile: Profile) -> Self {
+        self.options.profile = profile;
+        self
+    }
+
+    pub fn user_agent(mut self, user_agent: UserAgent) -> Self {
+        self.options.user_agent = user_agent;
+        self
+    }
+
+    /// The window to show the session in, see [`authenticate`](crate::authenticate).
+    pub fn parent(mut self, window: &'a impl HasWindowHandle) -> Self {
+        self.parent = Some(Parent::Window(window));
+        self
+    }
+
+    /// Adds the web view to a GTK container instead.
+    #[cfg(target_os = "linux")]
+    pub fn container(mut self, container: &impl IsA<Container>) -> Self {
+        self.parent = Some(Parent::Container(container.as_ref().clone()));
+        self
+    }
+
+    /// Opens a window of its own instead, see [`crate::window`].
+    #[cfg(target_os = "linux")]
+    pub fn managed_window(
+        mut self,
+        window_options: WindowOptions,
+        transient_for: Option<&gtk::Window>,
+    ) -> Self {
+        self.parent = Some(Parent::ManagedWindow {
+            window_options,
+            transient_for: transient_for.cloned(),
+        });
+        self
+    }
+
+    /// The appearance of the window opened when there's no parent, or the web view can't be
+    /// embedded into it, like on Wayland.
+    #[cfg(target_os = "linux")]
+    pub fn window_options(mut self, window_options: WindowOptions) -> Self {
+        self.window_options = window_options;
+        self
+    }
+
+    /// A GTK window for the window opened in place of the parent to stay on top of. Windows of
+    /// other toolkits can't take that role, so this isn't derived from the parent.
+    #[cfg(target_os = "linux")]
+    pub fn transient_for(mut self, window: &gtk::Window) -> Self {
+        self.transient_for = Some(window.clone());
+        self
+    }
+
+    /// Called with everything going on in the session, to show progress, log diagnostics or
+    /// update the application's own user interface. Every handler set is called, in the order
+    /// they were set.
+    pub fn on_event(mut self, handler: impl Fn(AuthEvent<'_>) + 'static) -> Self {
+        self.events.add(handler);
+        self
+    }
+
+    pub fn build(self) -> Result<AuthSession<'a>, Error> {
+        #[cfg(target_os = "linux")]
+        let parent = self.parent.unwrap_or_else(|| Parent::ManagedWindow {
+            window_options: self.window_options.clone(),
+            transient_for: self.transient_for.clone(),
+        });
+        #[cfg(not(target_os = "linux"))]
+        let parent = self.parent.ok_or(Error::IncompleteSession("parent"))?;
+
+        Ok(AuthSession {
+            auth_url: self.auth_url.ok_or(Error::IncompleteSession("url"))?,
+            callback_matcher: self
+                .callback_matcher
+                .ok_or(Error::IncompleteSession("callback_matcher"))?,
+            options: self.options,
+            parent,
+            events: self.events,
+            #[cfg(target_os = "linux")]
+            window_options: self.window_options,
+            #[cfg(target_os = "linux")]
+            transient_for: self.transient_for,
+        })
+    }
+
+    pub fn start(
+        self,
+        callback: impl FnOnce(Result<Url, Error>) + 'static,
+    ) -> Result<CancelToken, Error> {
+        self.build()?.start(callback)
+    }
+
+    pub async fn start_async(self) -> Result<Url, Error> {
+        self.build()?.start_async().await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{cell::RefCell, rc::Rc};
+
+    use super::*;
+
+    #[test]
+    fn event_handlers() {
+        let calls = Rc::new(RefCell::new(Vec::new()));
+        let first = calls.clone();
+        let second = calls.clone();
+        let mut builder = AuthSession::builder()
+            .on_event(move |event| {
+                if let AuthEvent::TitleChanged(title) = event {
+                    first.borrow_mut().push(format!("first {title}"));
+                }
+            })
+            .on_event(move |event| {
+                if let AuthEvent::TitleChanged(title) = event {
+                    second.borrow_mut().push(format!("second {title}"));
+                }
+            });
+        let third = calls.clone();
+        builder.events.add(move |event| {
+            if let AuthEvent::TitleChanged(title) = event {
+                third.borrow_mut().push(format!("third {title}"));
+            }
+        });
+
+        builder.events.emit(AuthEvent::TitleChanged("Sign in"));
+        assert_eq!(
+            *calls.borrow(),
+            ["first Sign in", "second Sign in", "third Sign in"]
+        );
+    }
+}
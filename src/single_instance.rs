@@ -0,0 +1,321 @@
+//! Forwarding of callback URLs from a freshly launched instance to the one already running.
+//!
+//! When the browser redirects to a private-use scheme, the desktop launches the registered handler
+//! (see `scheme_handler`) with the URL as an argument, which usually means a second copy of the
+//! application. Calling [`startup`] early in `main` hands the URL over to the running instance
+//! through a Unix domain socket, where it completes the waiting session just like
+//! [`deliver_callback_url`](crate::deliver_callback_url).
+
+use std::{
+    io::{BufRead, BufReader, ErrorKind, Read, Write},
+    os::unix::{
+        fs::{DirBuilderExt, MetadataExt, OpenOptionsExt, PermissionsExt},
+        net::{UnixListener, UnixStream},
+    },
+    path::{Path, PathBuf},
+    sync::{
+        Arc,
+        atomic::{AtomicBool, Ordering},
+    },
+    time::Duration,
+};
+
+use crate::{CallbackMatcher, Error};
+
+/// URLs are short, anything longer than this is not something we sent.
+const MAX_URL_LENGTH: u64 = 64 * 1024;
+/// How long a connected instance may take to send the URL before it's dropped.
+const RECEIVE_TIMEOUT: Duration = Duration::from_secs(2);
+/// How often to try again connecting to an instance that bound the socket first.
+const BIND_ATTEMPTS: u32 = 3;
+
+/// The outcome of [`startup`].
+pub enum Startup {
+    /// No other instance is running. The listener forwards URLs sent by later instances to the
+    /// waiting sessions for as long as it is kept alive.
+    ///
+    /// `callback_url` is the callback URL passed to this process, if any. There can't be a session
+    /// waiting for it, since it was started by a process that's no longer running.
+    Primary {
+        listener: CallbackListener,
+        callback_url: Option<url::Url>,
+    },
+    /// The callback URL was handed to the running instance, this process should exit now.
+    Forwarded,
+    /// Another instance is running, but there was no callback URL to forward.
+    Secondary,
+}
+
+/// Checks the command line arguments for a callback URL and forwards it to the running instance
+/// of the application identified by `app_id`, or becomes that instance if there is none.
+pub fn startup(
+    app_id: &str,
+    callback_matcher: impl Into<CallbackMatcher>,
+) -> Result<Startup, Error> {
+    let callback_matcher = callback_matcher.into();
+    let callback_url = std::env::args().skip(1).find_map(|argument| {
+        url::Url::parse(&argument)
+            .ok()
+            .filter(|url| callback_matcher.matches(url))
+    });
+    startup_with_url(app_id, callback_url)
+}
+
+/// Like [`startup`], but with the callback URL already extracted from the arguments.
+pub fn startup_with_url(app_id: &str, callback_url: Option<url::Url>) -> Result<Startup, Error> {
+    startup_at(socket_path(app_id)?, callback_url)
+}
+
+fn startup_at(path: PathBuf, callback_url: Option<url::Url>) -> Result<Startup, Error> {
+    // Instances starting at the same time would otherwise both remove and bind the socket.
+    let _lock = lock(&path)?;
+    let mut attempt = 1;
+    loop {
+        match UnixStream::connect(&path) {
+            Ok(mut stream) => {
+                let Some(callback_url) = callback_url else {
+                    return Ok(Startup::Secondary);
+                };
+                tracing::debug!("Forwarding {callback_url} to the running instance");
+                stream.write_all(callback_url.as_str().as_bytes())?;
+                stream.write_all(b"\n")?;
+                return Ok(Startup::Forwarded);
+            }
+            Err(err)
+                if matches!(
+                    err.kind(),
+                    ErrorKind::NotFound | ErrorKind::ConnectionRefused
+                ) =>
+            {
+                // A leftover from an instance that didn't shut down cleanly.
+                if err.kind() == ErrorKind::ConnectionRefused {
+                    match std::fs::remove_file(&path) {
+                        Ok(()) => {}
+                        Err(err) if err.kind() == ErrorKind::NotFound => {}
+                        Err(err) => return Err(err.into()),
+                    }
+                }
+                match CallbackListener::bind(path.clone()) {
+                    Ok(listener) => {
+                        return Ok(Startup::Primary {
+                            listener,
+                            callback_url,
+                        });
+                    }
+                    // Another instance, one not taking the lock, won.
+                    Err(Error::Io(err))
+                        if err.kind() == ErrorKind::AddrInUse && attempt < BIND_ATTEMPTS =>
+                    {
+                        attempt += 1;
+                    }
+                    Err(err) => return Err(err),
+                }
+            }
+            Err(err) => return Err(err.into()),
+        }
+    }
+}
+
+/// Locks the file next to the socket, which stays in place so everyone locks the same one.
+fn lock(path: &Path) -> Result<std::fs::File, Error> {
+    let mut lock_path = path.as_os_str().to_owned();
+    lock_path.push(".lock");
+    let file = std::fs::OpenOptions::new()
+        .create(true)
+        .truncate(false)
+        .write(true)
+        .mode(0o600)
+        .open(lock_path)?;
+    file.lock()?;
+    Ok(file)
+}
+
+/// Receives callback URLs from other instances. Stops listening when dropped.
+pub struct CallbackListener {
+    path: PathBuf,
+    stopped: Arc<AtomicBool>,
+}
+
+impl CallbackListener {
+    fn bind(path: PathBuf) -> Result<Self, Error> {
+        let listener = UnixListener::bind(&path)?;
+        // The directory is private already, this is just belt and braces.
+        std::fs::set_permissions(&path, std::fs::Permissions::from_mode(0o600))?;
+
+        let stopped = Arc::new(AtomicBool::new(false));
+        let thread_stopped = stopped.clone();
+        std::thread::Builder::new()
+            .name("webauth-single-instance".to_string())
+            .spawn(move || {
+                for stream in listener.incoming() {
+                    if thread_stopped.load(Ordering::SeqCst) {
+                        break;
+                    }
+                    match stream {
+                        Ok(stream) => receive(stream),
+                        Err(err) => tracing::debug!("Failed accepting connection: {err}"),
+                    }
+                }
+            })?;
+
+        Ok(Self { path, stopped })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for CallbackListener {
+    fn drop(&mut self) {
+        self.stopped.store(true, Ordering::SeqCst);
+        // Don't remove the socket of an instance starting right now.
+        let _lock = lock(&self.path)
+            .inspect_err(|err| tracing::debug!("Failed locking the socket: {err}"))
+            .ok();
+        // Wake up the listener thread blocked in accept().
+        UnixStream::connect(&self.path).ok();
+        std::fs::remove_file(&self.path).ok();
+    }
+}
+
+fn receive(stream: UnixStream) {
+    // Don't let a client that never sends anything block the listener.
+    if let Err(err) = stream.set_read_timeout(Some(RECEIVE_TIMEOUT)) {
+        tracing::debug!("Failed setting read timeout: {err}");
+        return;
+    }
+    let mut line = String::new();
+    if let Err(err) = BufReader::new(stream.take(MAX_URL_LENGTH)).read_line(&mut line) {
+        tracing::debug!("Failed reading forwarded URL: {err}");
+        return;
+    }
+    let line = line.trim_end();
+    if line.is_empty() {
+        return;
+    }
+    match url::Url::parse(line) {
+        Ok(url) => {
+            crate::deliver_callback_url(&url);
+        }
+        Err(err) => tracing::debug!("Ignoring invalid forwarded URL {line:?}: {err}"),
+    }
+}
+
+fn socket_path(app_id: &str) -> Result<PathBuf, Error> {
+    if app_id.is_empty() || app_id.contains(['/', '\0']) {
+        return Err(Error::InvalidAppId(app_id.to_string()));
+    }
+    Ok(runtime_dir()?.join(format!("{app_id}.webauth")))
+}
+
+/// `$XDG_RUNTIME_DIR`, or a directory only accessible by the current user in the temporary
+/// directory where that isn't set, so other users can't take over the socket.
+fn runtime_dir() -> Result<PathBuf, Error> {
+    if let Some(dir) = std::env::var_os("XDG_RUNTIME_DIR").map(PathBuf::from)
+        && dir.is_absolute()
+    {
+        return Ok(dir);
+    }
+
+    // SAFETY: getuid has no preconditions and can't fail.
+    let uid = unsafe { libc::getuid() };
+    let dir = std::env::temp_dir().join(format!("webauth-{uid}"));
+    match std::fs::DirBuilder::new().mode(0o700).create(&dir) {
+        Ok(()) => {}
+        Err(err) if err.kind() == ErrorKind::AlreadyExists => {}
+        Err(err) => return Err(err.into()),
+    }
+    // It might have been created by someone else in the first place.
+    let metadata = std::fs::symlink_metadata(&dir)?;
+    if !metadata.is_dir() || metadata.uid() != uid || metadata.mode() & 0o077 != 0 {
+        return Err(std::io::Error::new(
+            ErrorKind::PermissionDenied,
+            format!("{} is not a private directory", dir.display()),
+        )
+        .into());
+    }
+    Ok(dir)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::mpsc;
+
+    use super::*;
+
+    #[test]
+    fn forwards_to_primary_despite_idle_client() {
+        let dir = std::env::temp_dir().join(format!("webauth-test-{}-socket", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("org.example.App.webauth");
+
+        let Startup::Primary { listener, .. } = startup_at(path.clone(), None).unwrap() else {
+            panic!("expected to become the primary instance");
+        };
+        let (sender, receiver) = mpsc::channel();
+        let _registration = crate::pending::register(
+            CallbackMatcher::from("org.example.single-instance"),
+            move |result| {
+                sender.send(result).ok();
+            },
+        );
+
+        // Connected, but never sending anything.
+        let _idle = UnixStream::connect(&path).unwrap();
+        let url = url::Url::parse("org.example.single-instance:/callback?code=1").unwrap();
+        assert!(matches!(
+            startup_at(path.clone(), Some(url.clone())).unwrap(),
+            Startup::Forwarded
+        ));
+        let received = receiver
+            .recv_timeout(RECEIVE_TIMEOUT + Duration::from_secs(3))
+            .unwrap()
+            .unwrap();
+        assert_eq!(received, url);
+
+        drop(listener);
+        assert!(!path.exists());
+        std::fs::remove_dir_all(&dir).ok();
+    }
+
+    #[test]
+    fn one_primary_among_concurrent_instances() {
+        let dir =
+            std::env::temp_dir().join(format!("webauth-test-{}-concurrent", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("org.example.App.webauth");
+        // A leftover from an instance that crashed.
+        drop(UnixListener::bind(&path).unwrap());
+
+        let barrier = Arc::new(std::sync::Barrier::new(8));
+        let instances: Vec<_> = (0..8)
+            .map(|_| {
+                let path = path.clone();
+                let barrier = barrier.clone();
+                std::thread::spawn(move || {
+                    barrier.wait();
+                    startup_at(path, None).unwrap()
+                })
+            })
+            .collect();
+        let results: Vec<_> = instances
+            .into_iter()
+            .map(|instance| instance.join().unwrap())
+            .collect();
+        assert_eq!(
+            results
+                .iter()
+                .filter(|result| matches!(result, Startup::Primary { .. }))
+                .count(),
+            1
+        );
+        assert!(matches!(
+            startup_at(path.clone(), None).unwrap(),
+            Startup::Secondary
+        ));
+
+        drop(results);
+        std::fs::remove_dir_all(&dir).ok();
+    }
+}
@@ -0,0 +1,160 @@
+//! Timers enforcing [`WebAuthOptions::timeout`](crate::WebAuthOptions::timeout) and
+//! [`WebAuthOptions::idle_timeout`](crate::WebAuthOptions::idle_timeout).
+
+use std::{
+    sync::mpsc::{self, RecvTimeoutError},
+    time::Duration,
+};
+
+use crate::Error;
+
+/// Calls a function on a background thread once the timeout elapsed, unless dropped before.
+pub(crate) struct ThreadTimer {
+    _stop: mpsc::Sender<()>,
+}
+
+impl ThreadTimer {
+    pub(crate) fn start(
+        timeout: Duration,
+        on_timeout: impl FnOnce() + Send + 'static,
+    ) -> Result<Self, Error> {
+        let (stop, stopped) = mpsc::channel();
+        std::thread::Builder::new()
+            .name("webauth-timeout".to_string())
+            .spawn(move || {
+                // Dropping the sender disconnects the channel, which wakes us up early.
+                if let Err(RecvTimeoutError::Timeout) = stopped.recv_timeout(timeout) {
+                    on_timeout();
+                }
+            })?;
+        Ok(Self { _stop: stop })
+    }
+}
+
+#[cfg(any(target_os = "linux", target_os = "windows", target_os = "android"))]
+pub(crate) use main_loop::MainLoopTimer;
+
+/// Calls a function on the GLib main loop of the current thread once the timeout elapsed, unless
+/// dropped before.
+#[cfg(target_os = "linux")]
+mod main_loop {
+    use std::{cell::Cell, rc::Rc, time::Duration};
+
+    use gtk::glib;
+
+    use crate::Error;
+
+    pub(crate) struct MainLoopTimer {
+        source: Option<glib::SourceId>,
+        fired: Rc<Cell<bool>>,
+    }
+
+    impl MainLoopTimer {
+        pub(crate) fn start(
+            timeout: Duration,
+            on_timeout: impl FnOnce() + 'static,
+        ) -> Result<Self, Error> {
+            let fired = Rc::new(Cell::new(false));
+            let source = glib::timeout_add_local_once(timeout, {
+                let fired = fired.clone();
+                move || {
+                    fired.set(true);
+                    on_timeout();
+                }
+            });
+            Ok(Self {
+                source: Some(source),
+                fired,
+            })
+        }
+    }
+
+    impl Drop for MainLoopTimer {
+        fn drop(&mut self) {
+            // The source is gone once it fired, removing it again would log a critical warning.
+            if let Some(source) = self.source.take()
+                && !self.fired.get()
+            {
+                source.remove();
+            }
+        }
+    }
+}
+
+/// Calls a function from the message loop of the current thread once the timeout elapsed, unless
+/// dropped before.
+#[cfg(target_os = "windows")]
+mod main_loop {
+    use std::{cell::RefCell, collections::HashMap, time::Duration};
+
+    use windows_sys::Win32::{
+        Foundation::HWND,
+        UI::WindowsAndMessaging::{KillTimer, SetTimer},
+    };
+
+    use crate::Error;
+
+    thread_local! {
+        /// Thread timers don't carry any user data, so the functions are looked up by timer ID.
+        static TIMERS: RefCell<HashMap<usize, Box<dyn FnOnce()>>> = RefCell::new(HashMap::new());
+    }
+
+    pub(crate) struct MainLoopTimer {
+        id: usize,
+    }
+
+    impl MainLoopTimer {
+        pub(crate) fn start(
+            timeout: Duration,
+            on_timeout: impl FnOnce() + 'static,
+        ) -> Result<Self, Error> {
+            let elapse = u32::try_from(timeout.as_millis()).unwrap_or(u32::MAX);
+            let id = unsafe { SetTimer(std::ptr::null_mut(), 0, elapse, Some(timer_proc)) };
+            if id == 0 {
+                return Err(std::io::Error::last_os_error().into());
+            }
+            TIMERS.with_borrow_mut(|timers| timers.insert(id, Box::new(on_timeout)));
+            Ok(Self { id })
+        }
+    }
+
+    impl Drop for MainLoopTimer {
+        fn drop(&mut self) {
+            if TIMERS
+                .with_borrow_mut(|timers| timers.remove(&self.id))
+                .is_some()
+            {
+                unsafe { KillTimer(std::ptr::null_mut(), self.id) };
+            }
+        }
+    }
+
+    unsafe extern "system" fn timer_proc(_hwnd: HWND, _message: u32, id: usize, _time: u32) {
+        // Timers are periodic, but we only want to fire once.
+        unsafe { KillTimer(std::ptr::null_mut(), id) };
+        // Call outside of the borrow, the function might start another timer.
+        if let Some(on_timeout) = TIMERS.with_borrow_mut(|timers| timers.remove(&id)) {
+            on_timeout();
+        }
+    }
+}
+
+/// There's no way to get back to the thread running the web view on Android without the NDK, so
+/// timeouts aren't supported there.
+#[cfg(target_os = "android")]
+mod main_loop {
+    use std::time::Duration;
+
+    use crate::Error;
+
+    pub(crate) struct MainLoopTimer;
+
+    impl MainLoopTimer {
+        pub(crate) fn start(
+            _timeout: Duration,
+            _on_timeout: impl FnOnce() + 'static,
+        ) -> Result<Self, Error> {
+            Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+        }
+    }
+}
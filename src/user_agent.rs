@@ -0,0 +1,118 @@
+/// The user agent the embedded web view identifies itself with. Ignored by
+/// `ASWebAuthenticationSession` and the system browser, which always use their own.
+#[derive(Debug, Clone, Default)]
+pub enum UserAgent {
+    /// The user agent of the engine, the same as in other applications using it. Identity
+    /// providers sniffing the user agent treat it like the browser the engine belongs to.
+    #[default]
+    EngineDefault,
+    /// The user agent of the engine, followed by the product token of the application. On Linux,
+    /// where the engine doesn't send client hints, the product is also sent as `Sec-CH-UA` brand
+    /// with the initial request. The engine's user agent is used unchanged on Android.
+    Append(Product),
+    /// Replaces the user agent entirely. Note that some identity providers refuse unknown user
+    /// agents or serve degraded pages.
+    Custom(String),
+}
+
+/// The application, as identified to the identity provider.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Product {
+    pub name: String,
+    pub version: String,
+}
+
+impl Product {
+    pub fn new(name: impl Into<String>, version: impl Into<String>) -> Self {
+        Self {
+            name: name.into(),
+            version: version.into(),
+        }
+    }
+
+    /// `name/version`, as product token in the `User-Agent` header
+    /// ([RFC 9110 §10.1.5](https://www.rfc-editor.org/rfc/rfc9110#section-10.1.5)). Characters
+    /// that aren't allowed in tokens are replaced by `_`.
+    pub fn user_agent_token(&self) -> String {
+        format!("{}/{}", token(&self.name), token(&self.version))
+    }
+
+    /// `"name";v="version"`, as brand in the `Sec-CH-UA` header
+    /// ([User-Agent Client Hints](https://wicg.github.io/ua-client-hints/#sec-ch-ua)).
+    pub fn sec_ch_ua_brand(&self) -> String {
+        format!(
+            "{};v={}",
+            quoted_string(&self.name),
+            quoted_string(&self.version)
+        )
+    }
+}
+
+fn token(value: &str) -> String {
+    value
+        .chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() || "!#$%&'*+-.^_`|~".contains(c) {
+                c
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
+/// A structured field string, which only allows printable ASCII.
+fn quoted_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            c if c.is_ascii_graphic() || c == ' ' => quoted.push(c),
+            _ => quoted.push('_'),
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tokens() {
+        assert_eq!(
+            Product::new("Example-App", "1.2.3").user_agent_token(),
+            "Example-App/1.2.3"
+        );
+        assert_eq!(
+            Product::new("Example App (beta)", "1.0/rc\r\n1").user_agent_token(),
+            "Example_App__beta_/1.0_rc__1"
+        );
+        assert_eq!(token("Grüße;q=1"), "Gr__e_q_1");
+        assert_eq!(token("!#$%&'*+-.^_`|~"), "!#$%&'*+-.^_`|~");
+    }
+
+    #[test]
+    fn quoted_strings() {
+        assert_eq!(quoted_string("Example App"), "\"Example App\"");
+        assert_eq!(quoted_string(r#"Say "hi"\"#), r#""Say \"hi\"\\""#);
+        assert_eq!(quoted_string("a\r\nb\tc\u{7f}ü"), "\"a__b_c__\"");
+    }
+
+    #[test]
+    fn sec_ch_ua_brand() {
+        assert_eq!(
+            Product::new("Example App", "1.2.3").sec_ch_ua_brand(),
+            r#""Example App";v="1.2.3""#
+        );
+        assert_eq!(
+            Product::new("\"Quoted\"", "1\n").sec_ch_ua_brand(),
+            r#""\"Quoted\"";v="1_""#
+        );
+    }
+}
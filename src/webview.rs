@@ -1,121 +1,1308 @@
-use std::{cell::RefCell, str::FromStr};
+#[cfg(target_os = "linux")]
+use std::sync::{Mutex, PoisonError};
+use std::{
+    borrow::Cow,
+    cell::{Cell, RefCell},
+    rc::{Rc, Weak},
+    str::FromStr,
+};
 
 #[cfg(target_os = "linux")]
-use gtk::{Container, glib::IsA};
+use gtk::{
+    Container,
+    glib::{Cast, IsA, thread_guard::ThreadGuard},
+};
+use raw_window_handle::HasWindowHandle;
+#[cfg(target_os = "linux")]
+use raw_window_handle::RawWindowHandle;
 use url::Url;
 #[cfg(target_os = "linux")]
 use wry::WebViewBuilderExtUnix;
-#[cfg(target_os = "windows")]
-use wry::raw_window_handle::HasWindowHandle;
 use wry::{
-    WebView, WebViewAttributes, WebViewBuilder,
-    http::{HeaderMap, HeaderName, HeaderValue},
+    NewWindowResponse, PageLoadEvent, WebContext, WebView, WebViewAttributes, WebViewBuilder,
+    http::{HeaderMap, HeaderName, HeaderValue, Response, StatusCode, header::CONTENT_TYPE},
 };
 
-use crate::Error;
+#[cfg(target_os = "windows")]
+use crate::profile::EphemeralDirectory;
+use crate::{
+    AuthEvent, AuthSession, BlockedNavigation, CallbackMatcher, CallbackParameters, Error,
+    NewWindowPolicy, Profile, UserAgent, session::Parent, timeout::MainLoopTimer,
+};
+
+#[cfg(target_os = "linux")]
+const SEC_CH_UA: HeaderName = HeaderName::from_static("sec-ch-ua");
 
+#[cfg(target_os = "linux")]
+const POPUP_WIDTH: i32 = 600;
+#[cfg(target_os = "linux")]
+const POPUP_HEIGHT: i32 = 700;
+
+const COMPLETION_PAGE: &str = "<!DOCTYPE html><html><head><meta charset=\"utf-8\"><title>Authentication complete</title></head>\
+<body></body></html>";
+
+/// Intercepts POST form submissions to `$ACTION` and hands the form fields to the IPC handler
+/// instead.
+#[cfg(feature = "saml")]
+const FORM_SUBMISSION_SCRIPT: &str = r#"(function () {
+    const key = (url) => url.protocol + "//" + url.host + url.pathname;
+    const action = key(new URL($ACTION));
+    const capture = (form) => {
+        let url;
+        try {
+            url = new URL(form.action, document.baseURI);
+        } catch (e) {
+            return false;
+        }
+        if (key(url) !== action || (form.method || "").toLowerCase() !== "post") {
+            return false;
+        }
+        window.ipc.postMessage(url.href + "\n" + new URLSearchParams(new FormData(form)).toString());
+        return true;
+    };
+    const submit = HTMLFormElement.prototype.submit;
+    HTMLFormElement.prototype.submit = function () {
+        if (!capture(this)) {
+            submit.call(this);
+        }
+    };
+    document.addEventListener("submit", (event) => {
+        if (capture(event.target)) {
+            event.preventDefault();
+        }
+    }, true);
+})();"#;
+
+/// Loads pages meant for new windows in the current one instead, see [`NewWindowPolicy::SameView`].
+const SAME_VIEW_SCRIPT: &str = r#"(function () {
+    window.open = function (url) {
+        if (url !== undefined && url !== null && String(url) !== "") {
+            window.location.assign(new URL(String(url), document.baseURI).href);
+        }
+        return window;
+    };
+    const retarget = (element) => {
+        if (element && (element.target || "").toLowerCase() === "_blank") {
+            element.target = "_self";
+        }
+    };
+    document.addEventListener("click", (event) => {
+        retarget(event.target instanceof Element ? event.target.closest("a, area") : null);
+    }, true);
+    document.addEventListener("submit", (event) => retarget(event.target), true);
+})();"#;
+
+/// Opens the web view in `window`.
+///
+/// On Linux, the web view is embedded into X11 windows. Wayland doesn't support embedding into
+/// the surfaces of other toolkits, so a separate window is opened instead (see [`crate::window`]),
+/// configured by [`window_options`](crate::AuthSessionBuilder::window_options). GTK applications
+/// can use [`authenticate_in_container`] to place it into a container of their own.
+///
+/// See [`AuthSession::builder`] for more settings.
 pub fn authenticate(
     auth_url: &url::Url,
-    callback_scheme: &str,
+    callback_matcher: impl Into<CallbackMatcher>,
+    options: crate::WebAuthOptions,
+    window: &impl HasWindowHandle,
+    callback: impl FnOnce(Result<url::Url, Error>) + 'static,
+) -> Result<CancelToken, Error> {
+    AuthSession::builder()
+        .url(auth_url.clone())
+        .callback_matcher(callback_matcher)
+        .options(options)
+        .parent(window)
+        .start(callback)
+}
+
+/// Like [`authenticate`], but with the web view added to a GTK container.
+#[cfg(target_os = "linux")]
+pub fn authenticate_in_container(
+    auth_url: &url::Url,
+    callback_matcher: impl Into<CallbackMatcher>,
     options: crate::WebAuthOptions,
-    #[cfg(target_os = "linux")] widget: &impl IsA<Container>,
-    #[cfg(not(target_os = "linux"))] window: &impl HasWindowHandle,
+    container: &impl IsA<Container>,
     callback: impl FnOnce(Result<url::Url, Error>) + 'static,
 ) -> Result<CancelToken, Error> {
+    AuthSession::builder()
+        .url(auth_url.clone())
+        .callback_matcher(callback_matcher)
+        .options(options)
+        .container(container)
+        .start(callback)
+}
+
+/// Like [`authenticate`], but for `response_mode=form_post`, where the authorization server
+/// makes the browser POST the response parameters to the redirect URI.
+///
+/// The callback scheme is registered as a custom protocol to receive the request body, so the
+/// matcher has to identify a private-use scheme, and the redirect URI needs an authority like
+/// `com.example.app://callback`. Requests to URLs without one, like `com.example.app:/callback`,
+/// never reach the custom protocol, so such matchers are rejected with
+/// [`Error::UnsupportedCallbackMatcher`]. Note that on Windows and Android, wry maps custom
+/// protocols to `http://<scheme>.<path>`, so the redirect URI has to be registered in that form.
+///
+/// Redirects with the parameters in the query are accepted as well, and error responses are
+/// returned as [`Error::Authorization`].
+pub fn authenticate_form_post(
+    auth_url: &url::Url,
+    callback_matcher: impl Into<CallbackMatcher>,
+    options: crate::WebAuthOptions,
+    window: &impl HasWindowHandle,
+    callback: impl FnOnce(Result<CallbackParameters, Error>) + 'static,
+) -> Result<CancelToken, Error> {
+    let session = AuthSession::builder()
+        .url(auth_url.clone())
+        .callback_matcher(callback_matcher)
+        .options(options)
+        .parent(window)
+        .build()?;
+    let scheme = custom_protocol_scheme(&session.callback_matcher)?;
+    start(session, Capture::CustomProtocol(scheme), move |result| {
+        callback(
+            result.and_then(|captured| {
+                CallbackParameters::from_form_post(captured.url, &captured.body)
+            }),
+        )
+    })
+}
+
+/// How the request ending the session is intercepted.
+pub(crate) enum Capture {
+    /// Navigating to a matching URL ends the session.
+    Navigation,
+    /// Requests to this custom protocol end the session, including their body.
+    CustomProtocol(String),
+    /// Submitting a form to a matching URL ends the session, with the form fields as body. This is
+    /// done by a script in the page, so it works for `https` URLs as well.
+    #[cfg(feature = "saml")]
+    FormSubmission(Url),
+}
+
+impl Capture {
+    fn callback_navigation(&self) -> CallbackNavigation {
+        match self {
+            Self::Navigation => CallbackNavigation::EndSession,
+            // The custom protocol handler sees the body.
+            Self::CustomProtocol(_) => CallbackNavigation::Allow,
+            // Only the form submission captured by the script carries the response, a plain GET
+            // doesn't.
+            #[cfg(feature = "saml")]
+            Self::FormSubmission(_) => CallbackNavigation::Ignore,
+        }
+    }
+}
+
+/// What navigating to a URL matched by the callback matcher does.
+#[derive(Clone, Copy)]
+enum CallbackNavigation {
+    EndSession,
+    /// Let the request through to where it's captured.
+    Allow,
+    /// Stay on the current page, and keep waiting.
+    #[cfg(feature = "saml")]
+    Ignore,
+}
+
+/// The request that ended the session.
+pub(crate) struct Captured {
+    pub url: Url,
+    /// `application/x-www-form-urlencoded` form fields, if any.
+    pub body: Vec<u8>,
+}
+
+type CallbackCell = RefCell<Option<Box<dyn FnOnce(Result<Captured, Error>)>>>;
+type CapturedCallback = Rc<CallbackCell>;
+/// Shared with the timers, so they can tear down the web view.
+type WebViewCell = RefCell<Option<WebView>>;
+
+pub(crate) fn start(
+    session: AuthSession<'_>,
+    capture: Capture,
+    callback: impl FnOnce(Result<Captured, Error>) + 'static,
+) -> Result<CancelToken, Error> {
+    #[cfg(target_os = "linux")]
+    if let Some((window_options, transient_for)) = managed_window(&session)? {
+        // Boxed, window::start calls back into this function with the callback wrapped again.
+        let callback: Box<dyn FnOnce(Result<Captured, Error>)> = Box::new(callback);
+        return crate::window::start(session, capture, window_options, transient_for, callback);
+    }
+
+    let AuthSession {
+        auth_url,
+        callback_matcher,
+        options,
+        parent,
+        events,
+        ..
+    } = session;
     tracing::trace!("Calling authenticate with URL: {auth_url}");
-    let callback_scheme = format!("{callback_scheme}:");
-    let callback = RefCell::new(Some(callback));
+    let events = Rc::new(events);
+    let finished_events = events.clone();
+    let callback: CapturedCallback = Rc::new(RefCell::new(Some(Box::new(
+        move |result: Result<Captured, Error>| {
+            finished_events.emit(AuthEvent::SessionFinished(
+                result.as_ref().map(|captured| &captured.url),
+            ));
+            callback(result);
+        },
+    ))));
+    let callback_navigation = capture.callback_navigation();
+    let shared_web_view = Rc::new(WebViewCell::default());
+
+    let timeout = options
+        .timeout
+        .map(|timeout| {
+            MainLoopTimer::start(
+                timeout,
+                on_timeout(Rc::downgrade(&callback), Rc::downgrade(&shared_web_view)),
+            )
+        })
+        .transpose()?;
+    let idle_timeout = options.idle_timeout;
+    let idle_timer = Rc::new(RefCell::new(
+        idle_timeout
+            .map(|timeout| {
+                MainLoopTimer::start(
+                    timeout,
+                    on_timeout(Rc::downgrade(&callback), Rc::downgrade(&shared_web_view)),
+                )
+            })
+            .transpose()?,
+    ));
+
+    let headers = options
+        .additional_header_fields
+        .into_iter()
+        .map(|(key, value)| Ok((HeaderName::from_str(&key)?, HeaderValue::from_str(&value)?)))
+        .collect::<Result<HeaderMap, crate::Error>>()?;
+    #[cfg(target_os = "linux")]
+    let headers = with_client_hints(headers, &options.user_agent)?;
+    // WebView2 only tells its user agent once the web view exists, so the page is loaded after
+    // appending to it.
+    #[cfg(target_os = "windows")]
+    let deferred_load = match &options.user_agent {
+        UserAgent::Append(product) => Some(product.user_agent_token()),
+        _ => None,
+    };
+    #[cfg(not(target_os = "windows"))]
+    let deferred_load: Option<String> = None;
+
+    // WebKitGTK ignores the context of incognito web views, and keeps their data in memory.
+    // WebView2 needs a user data folder for its browser process in any case.
+    #[cfg(target_os = "windows")]
+    let ephemeral_directory = options
+        .prefers_ephemeral_web_browser_session
+        .then(EphemeralDirectory::create)
+        .transpose()?;
+    #[cfg(target_os = "windows")]
+    let ephemeral_data_directory = ephemeral_directory
+        .as_ref()
+        .map(|directory| directory.path().to_path_buf());
+    #[cfg(not(target_os = "windows"))]
+    let ephemeral_data_directory = None;
+    let data_directory = match &options.profile {
+        _ if options.prefers_ephemeral_web_browser_session => ephemeral_data_directory,
+        Profile::Directory(directory) => {
+            std::fs::create_dir_all(directory)?;
+            Some(directory.clone())
+        }
+        Profile::Shared => None,
+    };
+    // A context of its own for every session, custom protocols can only be registered once.
+    let mut web_context = data_directory.map(|directory| WebContext::new(Some(directory)));
 
     let attributes = WebViewAttributes {
-        user_agent: Some("WebAuth".to_string()),
+        context: web_context.as_mut(),
+        user_agent: user_agent(&options.user_agent),
         incognito: options.prefers_ephemeral_web_browser_session,
         focused: true,
         ..Default::default()
     };
 
     #[cfg(target_os = "linux")]
-    let inner_container = widget.clone();
+    let inner_container = match &parent {
+        Parent::Container(container) => Some(container.clone()),
+        _ => None,
+    };
+    let navigation_policy = options.navigation_policy;
+    let new_window_navigation_policy = navigation_policy.clone();
+    let navigation_auth_url = auth_url.clone();
+    let new_window_auth_url = auth_url.clone();
+    let capture_callback_matcher = callback_matcher.clone();
+    #[cfg(feature = "saml")]
+    let capture_auth_url = auth_url.clone();
 
-    let builder = WebViewBuilder::new_with_attributes(attributes)
-        .with_navigation_handler(move |url| {
-            if url.starts_with(&callback_scheme)
-                && let Some(callback) = callback.take()
-            {
-                callback(Url::parse(&url).map_err(crate::Error::InvalidUrlInResponse));
-                false
-            } else {
-                true
+    let token_callback = callback.clone();
+    let navigation_callback = callback.clone();
+    let navigation_web_view = Rc::downgrade(&shared_web_view);
+    let navigation_idle_timer = Rc::downgrade(&idle_timer);
+    let navigation_events = events.clone();
+    let title_events = events.clone();
+    let page_load_events = events.clone();
+    // Shared with popups, which can end the session as well.
+    let navigation_handler: Rc<dyn Fn(String) -> bool> = Rc::new(move |url| {
+        if let (Some(timeout), Some(idle_timer)) = (idle_timeout, navigation_idle_timer.upgrade()) {
+            // Replacing the timer stops the previous one.
+            let timer = MainLoopTimer::start(
+                timeout,
+                on_timeout(
+                    Rc::downgrade(&navigation_callback),
+                    navigation_web_view.clone(),
+                ),
+            );
+            match timer {
+                Ok(timer) => *idle_timer.borrow_mut() = Some(timer),
+                Err(err) => tracing::warn!("Failed restarting the idle timeout: {err}"),
+            }
+        }
+        // Neither the allowlist nor the blocklist can be checked without an origin.
+        let Ok(url) = Url::parse(&url) else {
+            tracing::debug!("Navigation to unparseable {url:?} blocked");
+            return false;
+        };
+        navigation_events.emit(AuthEvent::NavigationRequested(&url));
+        if !callback_matcher.matches(&url) {
+            if navigation_policy.allows(&navigation_auth_url, &url) {
+                navigation_events.emit(AuthEvent::NavigationAllowed(&url));
+                return true;
+            }
+            tracing::debug!("Navigation to {url} blocked");
+            if navigation_policy.on_blocked == BlockedNavigation::OpenInBrowser {
+                open_in_browser(&url);
+            }
+            navigation_events.emit(AuthEvent::NavigationBlocked(&url));
+            return false;
+        }
+        match callback_navigation {
+            CallbackNavigation::EndSession => {}
+            CallbackNavigation::Allow => {
+                navigation_events.emit(AuthEvent::CallbackMatched(&url));
+                return true;
+            }
+            #[cfg(feature = "saml")]
+            CallbackNavigation::Ignore => {
+                tracing::debug!("Ignoring navigation to {url}, waiting for a form submission");
+                return false;
             }
+        }
+        navigation_events.emit(AuthEvent::CallbackMatched(&url));
+        if let Some(callback) = navigation_callback.take() {
+            callback(Ok(Captured {
+                url,
+                body: Vec::new(),
+            }));
+        }
+        false
+    });
+
+    // Set up for popups as well. Custom protocols are registered with the web context, which
+    // popups share, so they need nothing else.
+    #[cfg(feature = "saml")]
+    let form_capture = match &capture {
+        Capture::FormSubmission(action) => {
+            let callback = callback.clone();
+            let events = events.clone();
+            let callback_matcher = capture_callback_matcher.clone();
+            Some(FormCapture {
+                script: FORM_SUBMISSION_SCRIPT.replace("$ACTION", &js_string(action.as_str())),
+                ipc_handler: Rc::new(move |request| {
+                    // Any script in the page can post messages, so only accept submissions to
+                    // the ACS URL from the identity provider's pages.
+                    let Some(captured) = form_submission(
+                        request.uri(),
+                        request.body(),
+                        &capture_auth_url,
+                        &callback_matcher,
+                    ) else {
+                        return;
+                    };
+                    if let Some(callback) = callback.take() {
+                        events.emit(AuthEvent::CallbackMatched(&captured.url));
+                        callback(Ok(captured));
+                    }
+                }),
+            })
+        }
+        _ => None,
+    };
+
+    #[cfg(target_os = "linux")]
+    let popups = Rc::new(RefCell::new(Vec::new()));
+    let new_window_policy = options.new_window_policy;
+    #[cfg(target_os = "linux")]
+    let popup_context = Mutex::new(ThreadGuard::new(PopupContext {
+        navigation_handler: navigation_handler.clone(),
+        callback: Rc::downgrade(&callback),
+        popups: Rc::downgrade(&popups),
+        user_agent: attributes.user_agent.clone(),
+        #[cfg(feature = "saml")]
+        form_capture: form_capture.clone(),
+    }));
+    // Popups fall back to the same view where they aren't supported.
+    let load_in_same_view = new_window_policy == NewWindowPolicy::SameView
+        || (new_window_policy == NewWindowPolicy::Popup && cfg!(not(target_os = "linux")));
+
+    let mut builder = WebViewBuilder::new_with_attributes(attributes)
+        .with_navigation_handler({
+            let navigation_handler = navigation_handler.clone();
+            move |url| navigation_handler(url)
+        })
+        .with_new_window_req_handler(move |url, features| {
+            match new_window_policy {
+                NewWindowPolicy::SystemBrowser => match Url::parse(&url) {
+                    Ok(url) if new_window_navigation_policy.allows(&new_window_auth_url, &url) => {
+                        open_in_browser(&url)
+                    }
+                    Ok(url) => tracing::debug!("New window for {url} blocked"),
+                    Err(err) => tracing::warn!("Invalid URL for new window {url}: {err}"),
+                },
+                #[cfg(target_os = "linux")]
+                NewWindowPolicy::Popup => {
+                    // Called on the GTK thread. The context is only read, so it's still good if
+                    // another thread panicked while holding the lock, and it's released before
+                    // opening the popup, which might ask for a new window again.
+                    let popup_context = popup_context
+                        .lock()
+                        .unwrap_or_else(PoisonError::into_inner)
+                        .get_ref()
+                        .clone();
+                    let opener = features.opener.webview;
+                    let builder = WebViewBuilder::new().with_related_view(opener.clone());
+                    match open_popup(&popup_context, builder, &opener) {
+                        Ok(webview) => return NewWindowResponse::Create { webview },
+                        Err(err) => tracing::warn!("Failed opening popup for {url}: {err}"),
+                    }
+                }
+                _ => tracing::debug!("New window for {url} denied"),
+            }
+            #[cfg(not(target_os = "linux"))]
+            let _ = features;
+            NewWindowResponse::Deny
         })
-        .with_headers(
-            options
-                .additional_header_fields
-                .into_iter()
-                .map(|(key, value)| {
-                    Ok((HeaderName::from_str(&key)?, HeaderValue::from_str(&value)?))
-                })
-                .collect::<Result<HeaderMap, crate::Error>>()?,
-        )
-        .with_url(auth_url.to_string())
         .with_document_title_changed_handler(move |title| {
             #[cfg(target_os = "linux")]
-            {
-                let obj: &gtk::glib::Object = gtk::glib::Cast::upcast_ref(inner_container.as_ref());
+            if let Some(inner_container) = &inner_container {
+                let obj: &gtk::glib::Object = gtk::glib::Cast::upcast_ref(inner_container);
                 if let Some(window) = gtk::glib::Cast::downcast_ref::<gtk::Window>(obj) {
                     use gtk::traits::GtkWindowExt;
 
                     window.set_title(&title);
                 }
             }
-            #[cfg(not(target_os = "linux"))]
-            {
-                // TODO
+            title_events.emit(AuthEvent::TitleChanged(&title));
+        })
+        .with_on_page_load_handler(move |event, url| {
+            let Ok(url) = Url::parse(&url) else {
+                return;
+            };
+            page_load_events.emit(match event {
+                PageLoadEvent::Started => AuthEvent::PageLoadStarted(&url),
+                PageLoadEvent::Finished => AuthEvent::PageLoadFinished(&url),
+            });
+        });
+
+    if load_in_same_view {
+        builder = builder.with_initialization_script(SAME_VIEW_SCRIPT);
+    }
+    if deferred_load.is_none() {
+        builder = builder
+            .with_headers(headers.clone())
+            .with_url(auth_url.as_str());
+    }
+
+    match capture {
+        Capture::Navigation => {}
+        Capture::CustomProtocol(scheme) => {
+            builder = builder.with_custom_protocol(scheme, move |_, request| {
+                let (parts, body) = request.into_parts();
+                tracing::trace!("Callback request {} {}", parts.method, parts.uri);
+                // Other requests to the scheme, like for a favicon, don't end the session.
+                match Url::parse(&parts.uri.to_string()) {
+                    Ok(url) if capture_callback_matcher.matches(&url) => {
+                        if let Some(callback) = callback.take() {
+                            callback(Ok(Captured { url, body }));
+                        }
+                        Response::builder()
+                            .header(CONTENT_TYPE, "text/html; charset=utf-8")
+                            .body(Cow::Borrowed(COMPLETION_PAGE.as_bytes()))
+                            .unwrap()
+                    }
+                    result => {
+                        tracing::debug!("Ignoring request to {}: {result:?}", parts.uri);
+                        Response::builder()
+                            .status(StatusCode::NOT_FOUND)
+                            .body(Cow::Borrowed(&[][..]))
+                            .unwrap()
+                    }
+                }
+            });
+        }
+        #[cfg(feature = "saml")]
+        Capture::FormSubmission(_) => {}
+    }
+    #[cfg(feature = "saml")]
+    if let Some(form_capture) = &form_capture {
+        builder = form_capture.apply(builder);
+    }
+
+    #[cfg(target_os = "linux")]
+    let mut window_watch = match &parent {
+        Parent::Container(container) => watch_window(
+            container.upcast_ref::<gtk::Widget>(),
+            &token_callback,
+            &shared_web_view,
+        ),
+        _ => Vec::new(),
+    };
+    #[cfg(target_os = "linux")]
+    let embedded = matches!(parent, Parent::Window(_));
+
+    let web_view = match parent {
+        #[cfg(target_os = "linux")]
+        Parent::Container(container) => builder.build_gtk(&container)?,
+        #[cfg(target_os = "linux")]
+        Parent::Window(window) => builder.build_as_child(&window.window_handle()?)?,
+        #[cfg(target_os = "linux")]
+        Parent::ManagedWindow { .. } => unreachable!("managed windows are handled above"),
+        #[cfg(not(target_os = "linux"))]
+        Parent::Window(window) => builder.build(&window.window_handle()?)?,
+    };
+    #[cfg(target_os = "linux")]
+    if embedded {
+        window_watch = watch_x11_parent(&web_view, &token_callback, &shared_web_view);
+    }
+    #[cfg(target_os = "windows")]
+    if let Some(product_token) = deferred_load {
+        append_to_user_agent(&web_view, &product_token)?;
+        web_view.load_url_with_headers(auth_url.as_str(), headers)?;
+    }
+    *shared_web_view.borrow_mut() = Some(web_view);
+
+    Ok(CancelToken {
+        #[cfg(target_os = "linux")]
+        window_watch,
+        #[cfg(target_os = "linux")]
+        popups,
+        _web_context: web_context,
+        #[cfg(target_os = "windows")]
+        _ephemeral_directory: ephemeral_directory,
+        web_view: shared_web_view,
+        timeout,
+        idle_timer,
+        callback: token_callback,
+    })
+}
+
+/// Adds the product of an appended user agent as `Sec-CH-UA` brand. Only WebKitGTK, which doesn't
+/// send client hints, needs it. Chromium-based engines do, and the header would replace their
+/// brand list for the initial request.
+#[cfg(target_os = "linux")]
+fn with_client_hints(mut headers: HeaderMap, user_agent: &UserAgent) -> Result<HeaderMap, Error> {
+    if let UserAgent::Append(product) = user_agent
+        && !headers.contains_key(SEC_CH_UA)
+    {
+        headers.insert(
+            SEC_CH_UA,
+            HeaderValue::from_str(&product.sec_ch_ua_brand())?,
+        );
+    }
+    Ok(headers)
+}
+
+/// The user agent to create the web view with, `None` for the engine's.
+fn user_agent(user_agent: &UserAgent) -> Option<String> {
+    match user_agent {
+        UserAgent::EngineDefault => None,
+        UserAgent::Custom(user_agent) => Some(user_agent.clone()),
+        #[cfg(target_os = "linux")]
+        UserAgent::Append(product) => {
+            use webkit2gtk::SettingsExt;
+
+            let engine_user_agent = webkit2gtk::Settings::new().user_agent()?;
+            Some(format!(
+                "{engine_user_agent} {}",
+                product.user_agent_token()
+            ))
+        }
+        // Appended once the web view exists.
+        #[cfg(target_os = "windows")]
+        UserAgent::Append(_) => None,
+        #[cfg(target_os = "android")]
+        UserAgent::Append(_) => {
+            tracing::debug!("Can't append to the user agent on Android, using the engine's");
+            None
+        }
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn append_to_user_agent(web_view: &WebView, product_token: &str) -> Result<(), Error> {
+    use webview2_com::{Microsoft::Web::WebView2::Win32::ICoreWebView2Settings2, take_pwstr};
+    use windows_core::{HSTRING, Interface, PWSTR};
+    use wry::WebViewExtWindows;
+
+    unsafe {
+        let settings = web_view
+            .webview()
+            .Settings()
+            .and_then(|settings| settings.cast::<ICoreWebView2Settings2>())
+            .map_err(std::io::Error::from)?;
+        let mut user_agent = PWSTR::null();
+        settings
+            .UserAgent(&mut user_agent)
+            .map_err(std::io::Error::from)?;
+        let user_agent = take_pwstr(user_agent);
+        settings
+            .SetUserAgent(&HSTRING::from(format!("{user_agent} {product_token}")))
+            .map_err(std::io::Error::from)?;
+    }
+    Ok(())
+}
+
+/// The window to open when the web view can't go into the given parent.
+#[cfg(target_os = "linux")]
+fn managed_window(
+    session: &AuthSession<'_>,
+) -> Result<Option<(crate::window::WindowOptions, Option<gtk::Window>)>, Error> {
+    Ok(match &session.parent {
+        Parent::ManagedWindow {
+            window_options,
+            transient_for,
+        } => Some((window_options.clone(), transient_for.clone())),
+        // Wayland doesn't allow embedding into the surfaces of other toolkits.
+        Parent::Window(window)
+            if !matches!(
+                window.window_handle()?.as_raw(),
+                RawWindowHandle::Xlib(_) | RawWindowHandle::Xcb(_)
+            ) =>
+        {
+            tracing::debug!("Can't embed the web view into this window, opening one of its own");
+            Some((
+                session.window_options.clone(),
+                session.transient_for.clone(),
+            ))
+        }
+        _ => None,
+    })
+}
+
+/// Opens `url` in the system browser, on a thread of its own, as the D-Bus call to the portal can
+/// block for a while and would freeze the web view meanwhile. Other schemes than `http` and
+/// `https` could launch any application registered for them, so these URLs are dropped.
+fn open_in_browser(url: &Url) {
+    if !matches!(url.scheme(), "http" | "https") {
+        tracing::warn!("Not opening {url} in the system browser, only http and https are allowed");
+        return;
+    }
+    let url = url.clone();
+    let result = std::thread::Builder::new()
+        .name("webauth-browser".to_string())
+        .spawn(move || {
+            if let Err(err) = open_url(&url) {
+                tracing::warn!("Failed opening {url} in the system browser: {err}");
             }
         });
+    if let Err(err) = result {
+        tracing::warn!("Failed opening the system browser: {err}");
+    }
+}
 
-    let web_view;
+fn open_url(url: &Url) -> Result<(), Error> {
     #[cfg(target_os = "linux")]
     {
-        web_view = builder.build_gtk(widget)?;
+        crate::browser::open_url(url)
     }
-    #[cfg(not(target_os = "linux"))]
+    #[cfg(target_os = "windows")]
     {
-        web_view = builder.build(window)?;
+        use windows_sys::Win32::{
+            System::Com::{
+                COINIT_APARTMENTTHREADED, COINIT_DISABLE_OLE1DDE, CoInitializeEx, CoUninitialize,
+            },
+            UI::{Shell::ShellExecuteW, WindowsAndMessaging::SW_SHOWNORMAL},
+        };
+
+        let operation: Vec<u16> = "open\0".encode_utf16().collect();
+        let file: Vec<u16> = url.as_str().encode_utf16().chain([0]).collect();
+        // Shell extensions handling the URL may use COM.
+        let initialized = unsafe {
+            CoInitializeEx(
+                std::ptr::null(),
+                (COINIT_APARTMENTTHREADED | COINIT_DISABLE_OLE1DDE) as u32,
+            )
+        } >= 0;
+        let result = unsafe {
+            ShellExecuteW(
+                std::ptr::null_mut(),
+                operation.as_ptr(),
+                file.as_ptr(),
+                std::ptr::null(),
+                std::ptr::null(),
+                SW_SHOWNORMAL,
+            )
+        };
+        if initialized {
+            unsafe { CoUninitialize() };
+        }
+        // Values up to 32 are error codes.
+        if result as isize > 32 {
+            Ok(())
+        } else {
+            Err(
+                std::io::Error::other(format!("ShellExecuteW failed with {}", result as isize))
+                    .into(),
+            )
+        }
     }
+    #[cfg(target_os = "android")]
+    {
+        let _ = url;
+        Err(std::io::Error::from(std::io::ErrorKind::Unsupported).into())
+    }
+}
 
-    Ok(CancelToken {
+/// Captures form submissions with a script handing them to the IPC handler, see
+/// [`Capture::FormSubmission`].
+#[cfg(feature = "saml")]
+#[derive(Clone)]
+struct FormCapture {
+    script: String,
+    ipc_handler: Rc<dyn Fn(wry::http::Request<String>)>,
+}
+
+#[cfg(feature = "saml")]
+impl FormCapture {
+    fn apply<'a>(&self, builder: WebViewBuilder<'a>) -> WebViewBuilder<'a> {
+        let ipc_handler = self.ipc_handler.clone();
+        builder
+            .with_initialization_script(self.script.as_str())
+            .with_ipc_handler(move |request| ipc_handler(request))
+    }
+}
+
+/// What popups need from the session that opened them.
+///
+/// The additional header fields aren't among them: popups load what the opener asks for, which
+/// the engine requests on its own.
+#[cfg(target_os = "linux")]
+#[derive(Clone)]
+struct PopupContext {
+    navigation_handler: Rc<dyn Fn(String) -> bool>,
+    callback: Weak<CallbackCell>,
+    popups: Weak<RefCell<Vec<Popup>>>,
+    user_agent: Option<String>,
+    #[cfg(feature = "saml")]
+    form_capture: Option<FormCapture>,
+}
+
+/// A window opened by the page, see [`NewWindowPolicy::Popup`].
+#[cfg(target_os = "linux")]
+struct Popup {
+    window: gtk::Window,
+    _web_view: WebView,
+    destroyed: Rc<Cell<bool>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for Popup {
+    fn drop(&mut self) {
+        use gtk::traits::GtkWindowExt;
+
+        if !self.destroyed.get() {
+            self.window.close();
+        }
+    }
+}
+
+/// Opens a popup window with a web view built from `builder`, and returns the WebKit view for
+/// the page to load into.
+#[cfg(target_os = "linux")]
+fn open_popup(
+    context: &PopupContext,
+    builder: WebViewBuilder<'_>,
+    opener: &webkit2gtk::WebView,
+) -> Result<webkit2gtk::WebView, Error> {
+    use gtk::{
+        glib::ObjectExt,
+        traits::{ContainerExt, GtkWindowExt, WidgetExt},
+    };
+    use wry::WebViewExtUnix;
+
+    let Some(popups) = context.popups.upgrade() else {
+        return Err(Error::Aborted);
+    };
+    let window = gtk::Window::builder()
+        .default_width(POPUP_WIDTH)
+        .default_height(POPUP_HEIGHT)
+        .destroy_with_parent(true)
+        .build();
+    if let Some(parent) = opener
+        .toplevel()
+        .and_then(|toplevel| toplevel.downcast::<gtk::Window>().ok())
+    {
+        window.set_transient_for(Some(&parent));
+        // A modal parent would keep the popup from receiving input otherwise.
+        window.set_modal(parent.is_modal());
+        window.set_position(gtk::WindowPosition::CenterOnParent);
+    }
+    let content = gtk::Box::new(gtk::Orientation::Vertical, 0);
+    window.add(&content);
+
+    let navigation_handler = context.navigation_handler.clone();
+    let callback = context.callback.clone();
+    let navigation_window = window.downgrade();
+    let title_window = window.downgrade();
+    let mut builder = builder
+        .with_navigation_handler(move |url| {
+            let allow = navigation_handler(url);
+            // The popup ended the session.
+            if !allow
+                && callback
+                    .upgrade()
+                    .is_none_or(|callback| callback.borrow().is_none())
+                && let Some(window) = navigation_window.upgrade()
+            {
+                window.close();
+            }
+            allow
+        })
+        .with_document_title_changed_handler(move |title| {
+            if let Some(window) = title_window.upgrade() {
+                window.set_title(&title);
+            }
+        })
+        .with_focused(true);
+    if let Some(user_agent) = &context.user_agent {
+        builder = builder.with_user_agent(user_agent);
+    }
+    #[cfg(feature = "saml")]
+    if let Some(form_capture) = &context.form_capture {
+        builder = form_capture.apply(builder);
+    }
+    let web_view = builder.build_gtk(&content)?;
+    let webkit_web_view = web_view.webview();
+
+    // Pages close their popups with `window.close()`.
+    let close_window = window.downgrade();
+    webkit_web_view.connect_local("close", false, move |_| {
+        if let Some(window) = close_window.upgrade() {
+            window.close();
+        }
+        None
+    });
+    let destroyed = Rc::new(Cell::new(false));
+    window.connect_destroy({
+        let destroyed = destroyed.clone();
+        let popups = Rc::downgrade(&popups);
+        move |window| {
+            destroyed.set(true);
+            if let Some(popups) = popups.upgrade() {
+                // Not while the list is being cleared.
+                if let Ok(mut popups) = popups.try_borrow_mut() {
+                    let popup = popups
+                        .iter()
+                        .position(|popup| popup.window == *window)
+                        .map(|index| popups.remove(index));
+                    // Dropped after releasing the borrow, in case it tears down anything else.
+                    drop(popups);
+                    drop(popup);
+                }
+            }
+        }
+    });
+    window.show_all();
+
+    popups.borrow_mut().push(Popup {
+        window,
         _web_view: web_view,
+        destroyed,
+    });
+    Ok(webkit_web_view)
+}
+
+fn on_timeout(
+    callback: Weak<CallbackCell>,
+    web_view: Weak<WebViewCell>,
+) -> impl FnOnce() + 'static {
+    move || end_session(&callback, &web_view, Error::TimedOut)
+}
+
+/// Tears down the web view and completes the session with `error`, unless it's already over.
+fn end_session(callback: &Weak<CallbackCell>, web_view: &Weak<WebViewCell>, error: Error) {
+    let Some(callback) = callback.upgrade().and_then(|callback| callback.take()) else {
+        return;
+    };
+    tracing::debug!("Authentication session ended: {error}");
+    if let Some(web_view) = web_view.upgrade() {
+        drop_web_view(&web_view);
+    }
+    callback(Err(error));
+}
+
+/// Tears down the web view. The X11 window wry embeds it into is gone already if its parent was
+/// destroyed, so errors from destroying it again are ignored.
+fn drop_web_view(web_view: &WebViewCell) {
+    #[cfg(target_os = "linux")]
+    gtk::gdk::error_trap_push();
+    drop(web_view.take());
+    #[cfg(target_os = "linux")]
+    gtk::gdk::error_trap_pop_ignored();
+}
+
+/// Ends the session with [`Error::UserCancelled`] when the widget hosting the web view is
+/// destroyed, or the user closes its window and the application doesn't keep it open.
+#[cfg(target_os = "linux")]
+fn watch_window(
+    widget: &gtk::Widget,
+    callback: &CapturedCallback,
+    web_view: &Rc<WebViewCell>,
+) -> Vec<WidgetWatch> {
+    use gtk::{
+        glib::{ObjectExt, ToValue},
+        traits::WidgetExt,
+    };
+
+    let mut widgets = vec![widget.clone()];
+    if let Some(toplevel) = widget.toplevel()
+        && toplevel != *widget
+    {
+        widgets.push(toplevel);
+    }
+
+    widgets
+        .into_iter()
+        .map(|widget| {
+            let destroyed = Rc::new(Cell::new(false));
+            let mut handlers = Vec::new();
+            handlers.push(widget.connect_destroy({
+                let destroyed = destroyed.clone();
+                let callback = Rc::downgrade(callback);
+                let web_view = Rc::downgrade(web_view);
+                move |_| {
+                    destroyed.set(true);
+                    end_session(&callback, &web_view, Error::UserCancelled);
+                }
+            }));
+            if widget.is::<gtk::Window>() {
+                let callback = Rc::downgrade(callback);
+                let web_view = Rc::downgrade(web_view);
+                // Connected after the default handlers, which only run unless the application's
+                // handlers kept the window open.
+                handlers.push(widget.connect_local("delete-event", true, move |_| {
+                    end_session(&callback, &web_view, Error::UserCancelled);
+                    Some(gtk::glib::Propagation::Proceed.to_value())
+                }));
+            }
+            WidgetWatch {
+                widget,
+                handlers,
+                destroyed,
+            }
+        })
+        .collect()
+}
+
+/// Like [`watch_window`], for a web view embedded into an X11 window with
+/// [`WebViewBuilder::build_as_child`]. Destroying the parent destroys the window wry created inside
+/// of it, which GDK only reports for foreign windows when asked to.
+#[cfg(target_os = "linux")]
+fn watch_x11_parent(
+    web_view: &WebView,
+    callback: &CapturedCallback,
+    web_view_cell: &Rc<WebViewCell>,
+) -> Vec<WidgetWatch> {
+    use gtk::{gdk::EventMask, traits::WidgetExt};
+    use wry::WebViewExtUnix;
+
+    let widget = web_view.webview();
+    if let Some(window) = widget.toplevel().and_then(|toplevel| toplevel.window()) {
+        window.set_events(window.events() | EventMask::STRUCTURE_MASK);
+    }
+    watch_window(widget.upcast_ref(), callback, web_view_cell)
+}
+
+/// Signal handlers connected to a widget, disconnected again when dropped.
+#[cfg(target_os = "linux")]
+struct WidgetWatch {
+    widget: gtk::Widget,
+    handlers: Vec<gtk::glib::SignalHandlerId>,
+    /// Destroying a widget disconnects all of its handlers already.
+    destroyed: Rc<Cell<bool>>,
+}
+
+#[cfg(target_os = "linux")]
+impl Drop for WidgetWatch {
+    fn drop(&mut self) {
+        use gtk::glib::ObjectExt;
+
+        if self.destroyed.get() {
+            return;
+        }
+        for handler in self.handlers.drain(..) {
+            self.widget.disconnect(handler);
+        }
+    }
+}
+
+/// The form submission in an IPC message from the page at `page`, if it's a submission to the
+/// ACS URL from a page with the origin of `auth_url`.
+#[cfg(feature = "saml")]
+fn form_submission(
+    page: &wry::http::Uri,
+    message: &str,
+    auth_url: &Url,
+    callback_matcher: &CallbackMatcher,
+) -> Option<Captured> {
+    // The script sends the form action URL, followed by the encoded form fields.
+    let (url, body) = message.split_once('\n')?;
+    let page = Url::parse(&page.to_string()).ok()?;
+    if page.origin() != auth_url.origin() {
+        tracing::warn!("Ignoring form submission from {page}, not the identity provider");
+        return None;
+    }
+    let url = Url::parse(url).ok()?;
+    if !callback_matcher.matches(&url) {
+        tracing::warn!("Ignoring form submission to {url}, not the ACS URL");
+        return None;
+    }
+    tracing::trace!("Form submitted to {url}");
+    Some(Captured {
+        url,
+        body: body.as_bytes().to_vec(),
     })
 }
 
+/// Quotes a string as JavaScript string literal.
+#[cfg(feature = "saml")]
+fn js_string(value: &str) -> String {
+    let mut quoted = String::with_capacity(value.len() + 2);
+    quoted.push('"');
+    for c in value.chars() {
+        match c {
+            '"' | '\\' => {
+                quoted.push('\\');
+                quoted.push(c);
+            }
+            // Angle brackets could close a surrounding script tag.
+            c if (c.is_ascii_graphic() || c == ' ') && !matches!(c, '<' | '>') => quoted.push(c),
+            c => {
+                let mut buffer = [0u16; 2];
+                for unit in c.encode_utf16(&mut buffer) {
+                    quoted.push_str(&format!("\\u{unit:04x}"));
+                }
+            }
+        }
+    }
+    quoted.push('"');
+    quoted
+}
+
+/// Custom protocols can only be registered for private-use schemes, and wry only passes on
+/// requests to URLs with an authority.
+fn custom_protocol_scheme(callback_matcher: &CallbackMatcher) -> Result<String, Error> {
+    let scheme = match callback_matcher {
+        CallbackMatcher::Scheme(scheme) => scheme.as_str(),
+        CallbackMatcher::SchemeHostPath { scheme, host, .. } if !host.is_empty() => scheme.as_str(),
+        CallbackMatcher::RedirectUri(redirect_uri)
+            if redirect_uri.host_str().is_some_and(|host| !host.is_empty()) =>
+        {
+            redirect_uri.scheme()
+        }
+        _ => return Err(Error::UnsupportedCallbackMatcher(callback_matcher.clone())),
+    };
+    if scheme.eq_ignore_ascii_case("http") || scheme.eq_ignore_ascii_case("https") {
+        return Err(Error::UnsupportedCallbackMatcher(callback_matcher.clone()));
+    }
+    Ok(scheme.to_ascii_lowercase())
+}
+
+/// Dropping this token tears down the web view. If the session is still running, the callback
+/// receives [`Error::Aborted`].
 pub struct CancelToken {
-    _web_view: WebView,
+    #[cfg(target_os = "linux")]
+    window_watch: Vec<WidgetWatch>,
+    #[cfg(target_os = "linux")]
+    popups: Rc<RefCell<Vec<Popup>>>,
+    web_view: Rc<WebViewCell>,
+    timeout: Option<MainLoopTimer>,
+    idle_timer: Rc<RefCell<Option<MainLoopTimer>>>,
+    callback: CapturedCallback,
+    // Dropped after the web view, in this order.
+    _web_context: Option<WebContext>,
+    #[cfg(target_os = "windows")]
+    _ephemeral_directory: Option<EphemeralDirectory>,
+}
+
+impl CancelToken {
+    /// Cancels the session, same as dropping the token.
+    pub fn cancel(self) {
+        drop(self);
+    }
+}
+
+impl Drop for CancelToken {
+    fn drop(&mut self) {
+        #[cfg(target_os = "linux")]
+        self.window_watch.clear();
+        // Taken out first, closing a popup removes it from the list.
+        #[cfg(target_os = "linux")]
+        drop(self.popups.take());
+        self.timeout.take();
+        self.idle_timer.take();
+        drop_web_view(&self.web_view);
+        // Call after tearing everything down, the callback might start another session.
+        if let Some(callback) = self.callback.take() {
+            tracing::trace!("Authentication session cancelled");
+            callback(Err(Error::Aborted));
+        }
+    }
 }
 
 pub async fn authenticate_async(
     auth_url: &url::Url,
-    callback_scheme: &str,
+    callback_matcher: impl Into<CallbackMatcher>,
+    options: crate::WebAuthOptions,
+    window: &impl HasWindowHandle,
+) -> Result<url::Url, Error> {
+    AuthSession::builder()
+        .url(auth_url.clone())
+        .callback_matcher(callback_matcher)
+        .options(options)
+        .parent(window)
+        .start_async()
+        .await
+}
+
+#[cfg(target_os = "linux")]
+pub async fn authenticate_in_container_async(
+    auth_url: &url::Url,
+    callback_matcher: impl Into<CallbackMatcher>,
     options: crate::WebAuthOptions,
-    #[cfg(target_os = "linux")] widget: &impl IsA<Container>,
-    #[cfg(not(target_os = "linux"))] window: &impl HasWindowHandle,
+    container: &impl IsA<Container>,
 ) -> Result<url::Url, Error> {
+    AuthSession::builder()
+        .url(auth_url.clone())
+        .callback_matcher(callback_matcher)
+        .options(options)
+        .container(container)
+        .start_async()
+        .await
+}
+
+pub async fn authenticate_form_post_async(
+    auth_url: &url::Url,
+    callback_matcher: impl Into<CallbackMatcher>,
+    options: crate::WebAuthOptions,
+    window: &impl HasWindowHandle,
+) -> Result<CallbackParameters, Error> {
     let (sender, receiver) = futures::channel::oneshot::channel();
 
-    let cancel_token = authenticate(
-        auth_url,
-        callback_scheme,
-        options,
-        #[cfg(target_os = "linux")]
-        widget,
-        #[cfg(not(target_os = "linux"))]
-        window,
-        move |result| {
+    let cancel_token =
+        authenticate_form_post(auth_url, callback_matcher, options, window, move |result| {
             sender.send(result).ok();
-        },
-    )?;
+        })?;
 
     let result = receiver.await.unwrap_or(Err(crate::Error::Aborted));
     drop(cancel_token);
 
     result
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn custom_protocol_schemes() {
+        let redirect_uri = |uri: &str| CallbackMatcher::RedirectUri(Url::parse(uri).unwrap());
+
+        assert_eq!(
+            custom_protocol_scheme(&redirect_uri("Com.Example.App://callback/path")).unwrap(),
+            "com.example.app"
+        );
+        assert_eq!(
+            custom_protocol_scheme(&CallbackMatcher::from("com.example.app")).unwrap(),
+            "com.example.app"
+        );
+        for unsupported in [
+            redirect_uri("com.example.app:authorized"),
+            redirect_uri("com.example.app:/callback"),
+            redirect_uri("https://example.com/callback"),
+            CallbackMatcher::https("example.com", "/callback"),
+            CallbackMatcher::SchemeHostPath {
+                scheme: "com.example.app".to_string(),
+                host: String::new(),
+                path: "/callback".to_string(),
+            },
+            CallbackMatcher::custom(|_| true),
+        ] {
+            assert!(
+                matches!(
+                    custom_protocol_scheme(&unsupported),
+                    Err(Error::UnsupportedCallbackMatcher(_))
+                ),
+                "{unsupported:?}"
+            );
+        }
+    }
+
+    #[cfg(feature = "saml")]
+    #[test]
+    fn form_submissions() {
+        let auth_url = Url::parse("https://idp.example.com/sso?SAMLRequest=abc").unwrap();
+        let acs = CallbackMatcher::RedirectUri(Url::parse("https://sp.example.com/acs").unwrap());
+        let page: wry::http::Uri = "https://idp.example.com/sso/login".parse().unwrap();
+        let message = "https://sp.example.com/acs\nSAMLResponse=abc&RelayState=x";
+
+        let captured = form_submission(&page, message, &auth_url, &acs).unwrap();
+        assert_eq!(captured.url.as_str(), "https://sp.example.com/acs");
+        assert_eq!(captured.body, b"SAMLResponse=abc&RelayState=x");
+
+        let other_page: wry::http::Uri = "https://ads.example.net/frame".parse().unwrap();
+        assert!(form_submission(&other_page, message, &auth_url, &acs).is_none());
+        assert!(
+            form_submission(
+                &page,
+                "https://evil.example.net/acs\nSAMLResponse=abc",
+                &auth_url,
+                &acs
+            )
+            .is_none()
+        );
+        assert!(form_submission(&page, "SAMLResponse=abc", &auth_url, &acs).is_none());
+    }
+
+    #[cfg(feature = "saml")]
+    #[test]
+    fn js_strings() {
+        assert_eq!(
+            js_string("https://sp.example.com/acs"),
+            "\"https://sp.example.com/acs\""
+        );
+        assert_eq!(js_string("a\"b\\c"), "\"a\\\"b\\\\c\"");
+        assert_eq!(
+            js_string("</script>\n\u{2028}ä😀"),
+            "\"\\u003c/script\\u003e\\u000a\\u2028\\u00e4\\ud83d\\ude00\""
+        );
+    }
+
+    /// What the check above is about: wry hands the request to the handler as `http::Uri`.
+    #[test]
+    fn custom_protocol_uris() {
+        assert!(
+            "com.example.app://callback/path?code=1"
+                .parse::<wry::http::Uri>()
+                .is_ok()
+        );
+        assert!(
+            "com.example.app:authorized?code=1"
+                .parse::<wry::http::Uri>()
+                .is_err()
+        );
+        assert!(
+            "com.example.app:/callback"
+                .parse::<wry::http::Uri>()
+                .is_err()
+        );
+    }
+}
@@ -0,0 +1,192 @@
+//! Authentication in a window created and owned by this crate, instead of a container provided by
+//! the application.
+//!
+//! The window is modal and transient for the given parent, if any, and closes itself once the
+//! session is over, no matter how it ended.
+
+use std::path::PathBuf;
+
+use gtk::{
+    Orientation,
+    glib::{Cast, IsA},
+    traits::{BoxExt, ContainerExt, GtkWindowExt, ImageExt, LabelExt, WidgetExt},
+};
+
+use crate::{
+    AuthEvent, AuthSession, CallbackMatcher, Error,
+    session::Parent,
+    webview::{self, CancelToken, Capture, Captured},
+};
+
+/// The appearance of the login window.
+#[derive(Debug, Clone)]
+pub struct WindowOptions {
+    pub title: String,
+    pub default_width: i32,
+    pub default_height: i32,
+    pub icon: Option<WindowIcon>,
+    /// Shows the host of the current page above it, and whether it was loaded over HTTPS, so
+    /// users can tell which site they're signing in to. It's updated once a page finished
+    /// loading, and empty before the first one did.
+    pub show_origin: bool,
+}
+
+/// A window icon. Unlike a `Pixbuf`, this can be sent to the thread running GTK.
+#[derive(Debug, Clone)]
+pub enum WindowIcon {
+    /// An icon from the icon theme.
+    Name(String),
+    /// An image file in any format supported by gdk-pixbuf.
+    File(PathBuf),
+}
+
+impl Default for WindowOptions {
+    fn default() -> Self {
+        Self {
+            title: "Sign In".to_string(),
+            default_width: 800,
+            default_height: 800,
+            icon: None,
+            show_origin: false,
+        }
+    }
+}
+
+pub fn authenticate(
+    auth_url: &url::Url,
+    callback_matcher: impl Into<CallbackMatcher>,
+    options: crate::WebAuthOptions,
+    window_options: WindowOptions,
+    parent: Option<&impl IsA<gtk::Window>>,
+    callback: impl FnOnce(Result<url::Url, Error>) + 'static,
+) -> Result<CancelToken, Error> {
+    AuthSession::builder()
+        .url(auth_url.clone())
+        .callback_matcher(callback_matcher)
+        .options(options)
+        .managed_window(window_options, parent.map(|parent| parent.as_ref()))
+        .start(callback)
+}
+
+/// Opens the window and starts `session` in it, replacing its parent.
+pub(crate) fn start(
+    mut session: AuthSession<'_>,
+    capture: Capture,
+    window_options: WindowOptions,
+    parent: Option<gtk::Window>,
+    callback: impl FnOnce(Result<Captured, Error>) + 'static,
+) -> Result<CancelToken, Error> {
+    let window = gtk::Window::builder()
+        .title(window_options.title.as_str())
+        .default_width(window_options.default_width)
+        .default_height(window_options.default_height)
+        .modal(true)
+        .destroy_with_parent(true)
+        .build();
+    if let Some(parent) = parent {
+        window.set_transient_for(Some(&parent));
+        window.set_position(gtk::WindowPosition::CenterOnParent);
+    }
+    match &window_options.icon {
+        Some(WindowIcon::Name(name)) => window.set_icon_name(Some(name)),
+        Some(WindowIcon::File(path)) => {
+            if let Err(err) = window.set_icon_from_file(path) {
+                tracing::warn!("Failed loading window icon {}: {err}", path.display());
+            }
+        }
+        None => {}
+    }
+    // The web view goes into a box rather than the window itself, so the page doesn't replace the
+    // configured title.
+    let content = gtk::Box::new(Orientation::Vertical, 0);
+    window.add(&content);
+    if window_options.show_origin {
+        let origin_bar = OriginBar::new();
+        content.pack_start(&origin_bar.container, false, false, 0);
+        // Loads that just started might still be redirected or blocked, only show where the page
+        // actually came from.
+        session.events.add(move |event| {
+            if let AuthEvent::PageLoadFinished(url) = event {
+                origin_bar.update(url);
+            }
+        });
+    }
+
+    session.parent = Parent::Container(content.upcast());
+
+    let close_window = window.clone();
+    let result = webview::start(session, capture, move |result| {
+        close_window.close();
+        callback(result);
+    });
+    match result {
+        Ok(cancel_token) => {
+            window.show_all();
+            Ok(cancel_token)
+        }
+        Err(err) => {
+            window.close();
+            Err(err)
+        }
+    }
+}
+
+/// A read-only display of the current page's origin.
+struct OriginBar {
+    container: gtk::Box,
+    icon: gtk::Image,
+    host: gtk::Label,
+}
+
+impl OriginBar {
+    fn new() -> Self {
+        let container = gtk::Box::new(Orientation::Horizontal, 6);
+        container.set_border_width(6);
+        let icon = gtk::Image::new();
+        let host = gtk::Label::new(None);
+        host.set_ellipsize(gtk::pango::EllipsizeMode::Start);
+        host.set_xalign(0.0);
+        container.pack_start(&icon, false, false, 0);
+        container.pack_start(&host, true, true, 0);
+        Self {
+            container,
+            icon,
+            host,
+        }
+    }
+
+    fn update(&self, url: &url::Url) {
+        let host = match (url.host_str(), url.port()) {
+            (Some(host), Some(port)) => format!("{host}:{port}"),
+            (Some(host), None) => host.to_string(),
+            (None, _) => url.scheme().to_string(),
+        };
+        self.host.set_text(&host);
+        self.host
+            .set_tooltip_text(Some(url.origin().ascii_serialization().as_str()));
+        let (icon_name, description) = if url.scheme() == "https" {
+            ("channel-secure-symbolic", "Secure connection")
+        } else {
+            ("channel-insecure-symbolic", "Insecure connection")
+        };
+        self.icon
+            .set_from_icon_name(Some(icon_name), gtk::IconSize::Menu);
+        self.icon.set_tooltip_text(Some(description));
+    }
+}
+
+pub async fn authenticate_async(
+    auth_url: &url::Url,
+    callback_matcher: impl Into<CallbackMatcher>,
+    options: crate::WebAuthOptions,
+    window_options: WindowOptions,
+    parent: Option<&impl IsA<gtk::Window>>,
+) -> Result<url::Url, Error> {
+    AuthSession::builder()
+        .url(auth_url.clone())
+        .callback_matcher(callback_matcher)
+        .options(options)
+        .managed_window(window_options, parent.map(|parent| parent.as_ref()))
+        .start_async()
+        .await
+}